@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS reindeer (
+  name TEXT PRIMARY KEY,
+  strength INT NOT NULL,
+  speed REAL NOT NULL,
+  height INT NOT NULL,
+  antler_width INT NOT NULL,
+  snow_magic_power INT NOT NULL,
+  favorite_food TEXT NOT NULL,
+  candies INT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS contests (
+  id SERIAL PRIMARY KEY,
+  held_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  participants TEXT[] NOT NULL,
+  winners JSONB NOT NULL,
+  contest JSONB NOT NULL
+);
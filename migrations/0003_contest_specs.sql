@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS contest_specs (
+  name TEXT PRIMARY KEY,
+  spec JSONB NOT NULL
+);
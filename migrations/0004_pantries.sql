@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS pantries (
+  user_name TEXT PRIMARY KEY,
+  pantry JSONB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS bakes (
+  id SERIAL PRIMARY KEY,
+  user_name TEXT NOT NULL,
+  baked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  recipe JSONB NOT NULL,
+  cookies BIGINT NOT NULL,
+  pantry_before JSONB NOT NULL,
+  pantry_after JSONB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS bakes_user_name ON bakes (user_name, baked_at);
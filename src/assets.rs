@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path as FsPath, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_ENCODING, ETAG, IF_NONE_MATCH},
+        HeaderValue, Method, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use shuttle_runtime::SecretStore;
+use subtle::ConstantTimeEq;
+use tracing::{debug, info};
+
+use crate::router::{self, secret, Error, HttpError};
+
+const PRECOMPRESSED: [&str; 2] = ["gz", "br"];
+const PARTIAL_SUFFIX: &str = ".partial";
+
+#[derive(Debug, Clone)]
+pub struct AssetsConfig {
+    pub root: PathBuf,
+    pub cache_control: String,
+    /// HTML changes with deploys, so by default it's always revalidated
+    pub html_cache_control: String,
+    pub listing: bool,
+    /// Upload and delete are disabled unless a token is configured
+    pub admin_token: Option<String>,
+}
+
+impl AssetsConfig {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        AssetsConfig {
+            root: secrets
+                .get("ASSETS_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("assets")),
+            cache_control: secrets
+                .get("ASSETS_CACHE_CONTROL")
+                .unwrap_or_else(|| "public, max-age=3600".to_string()),
+            html_cache_control: secrets
+                .get("ASSETS_HTML_CACHE_CONTROL")
+                .unwrap_or_else(|| "no-cache".to_string()),
+            listing: secret(secrets, "ASSETS_LISTING").unwrap_or_default(),
+            admin_token: secrets.get("ASSETS_ADMIN_TOKEN"),
+        }
+    }
+}
+
+struct Fingerprint {
+    len: u64,
+    modified: SystemTime,
+    hash: String,
+}
+
+pub struct Assets {
+    pub config: AssetsConfig,
+    // Hashing a file is only redone when its size or modification time changes
+    fingerprints: Mutex<HashMap<PathBuf, Fingerprint>>,
+}
+
+impl Assets {
+    pub fn new(config: AssetsConfig) -> Self {
+        info!(
+            root = ?config.root,
+            listing = config.listing,
+            admin = config.admin_token.is_some()
+        );
+        Assets {
+            config,
+            fingerprints: Mutex::default(),
+        }
+    }
+
+    /// Maps an already decoded request path below the root, refusing anything
+    /// that could escape it
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let mut resolved = self.config.root.clone();
+        for component in FsPath::new(path.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        Some(resolved)
+    }
+
+    async fn etag(&self, path: &FsPath) -> Option<String> {
+        let metadata = tokio::fs::metadata(path).await.ok()?;
+        let modified = metadata.modified().ok()?;
+        if let Some(fingerprint) = self.fingerprints.lock().unwrap().get(path) {
+            if fingerprint.len == metadata.len() && fingerprint.modified == modified {
+                return Some(fingerprint.hash.clone());
+            }
+        }
+
+        let file = path.to_path_buf();
+        let hash = tokio::task::spawn_blocking(move || {
+            std::fs::read(file).map(|content| sha256::digest(content.as_slice()))
+        })
+        .await
+        .ok()?
+        .ok()?;
+        self.fingerprints.lock().unwrap().insert(
+            path.to_path_buf(),
+            Fingerprint {
+                len: metadata.len(),
+                modified,
+                hash: hash.clone(),
+            },
+        );
+        Some(hash)
+    }
+
+    fn forget(&self, path: &FsPath) {
+        self.fingerprints.lock().unwrap().remove(path);
+    }
+
+    fn cache_control(&self, path: &FsPath) -> &str {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => &self.config.html_cache_control,
+            _ => &self.config.cache_control,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ListingEntry {
+    name: String,
+    size: u64,
+    directory: bool,
+}
+
+async fn listing(dir: &FsPath) -> Result<Vec<ListingEntry>, Error> {
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(dir)
+        .await
+        .context("Failed to read directory")?;
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .context("Failed to read directory entry")?
+    {
+        // Uploads still being written
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SUFFIX)
+        {
+            continue;
+        }
+        let metadata = entry.metadata().await.context("Failed to read metadata")?;
+        entries.push(ListingEntry {
+            name: entry.file_name().to_string_lossy().to_string(),
+            size: metadata.len(),
+            directory: metadata.is_dir(),
+        });
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn if_none_match(header: Option<&HeaderValue>, etag: &str) -> bool {
+    let Some(header) = header.and_then(|header| header.to_str().ok()) else {
+        return false;
+    };
+    header
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Wraps `ServeDir` with strong ETags, `Cache-Control` and optional directory
+/// listings. Ranges and precompressed variants are left to `ServeDir`.
+pub async fn caching(
+    State(state): State<Arc<router::State>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(next.run(request).await);
+    }
+
+    let assets = &state.assets;
+    // Same single decoding `ServeDir` does, admin routes get theirs from `Path`
+    let path = percent_decode_str(request.uri().path())
+        .decode_utf8()
+        .ok()
+        .and_then(|path| assets.resolve(&path));
+    let Some(path) = path else {
+        return Err(HttpError::not_found("Asset not found").into());
+    };
+    let is_dir = tokio::fs::metadata(&path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+    if is_dir && assets.config.listing && !path.join("index.html").exists() {
+        return Ok(Json(listing(&path).await?).into_response());
+    }
+
+    let if_none_match_header = request.headers().get(IF_NONE_MATCH).cloned();
+    let mut response = next.run(request).await;
+    if !response.status().is_success() || is_dir {
+        return Ok(response);
+    }
+
+    let Some(hash) = assets.etag(&path).await else {
+        return Ok(response);
+    };
+    // Each precompressed variant is a different representation and needs its own tag
+    let etag = match response.headers().get(CONTENT_ENCODING) {
+        Some(encoding) => format!("\"{}-{}\"", hash, encoding.to_str().unwrap_or_default()),
+        None => format!("\"{}\"", hash),
+    };
+    debug!(?path, etag);
+
+    let cache_control =
+        HeaderValue::from_str(assets.cache_control(&path)).context("Invalid Cache-Control")?;
+    let etag = HeaderValue::from_str(&etag).context("Invalid ETag")?;
+    if if_none_match(
+        if_none_match_header.as_ref(),
+        etag.to_str().unwrap_or_default(),
+    ) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(ETAG, etag), (CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+
+    let headers = response.headers_mut();
+    headers.insert(ETAG, etag);
+    headers.insert(CACHE_CONTROL, cache_control);
+    Ok(response)
+}
+
+fn authorize(
+    assets: &Assets,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<(), HttpError> {
+    let Some(token) = &assets.config.admin_token else {
+        return Err(HttpError::new(
+            StatusCode::FORBIDDEN,
+            "Asset uploads are disabled",
+        ));
+    };
+
+    match authorization {
+        Some(TypedHeader(Authorization(bearer)))
+            if bool::from(bearer.token().as_bytes().ct_eq(token.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(HttpError::new(
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid admin token",
+        )),
+    }
+}
+
+fn admin_path(assets: &Assets, path: &str) -> Result<PathBuf, HttpError> {
+    assets
+        .resolve(path)
+        .filter(|resolved| resolved != &assets.config.root)
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "Invalid asset path"))
+}
+
+// A stale precompressed copy would otherwise keep being served for the old content
+async fn remove_variants(path: &FsPath) {
+    for ext in PRECOMPRESSED {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(format!(".{}", ext));
+        let _ = tokio::fs::remove_file(variant).await;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Uploaded {
+    path: String,
+    size: usize,
+    etag: String,
+}
+
+pub async fn upload(
+    State(state): State<Arc<router::State>>,
+    Path(path): Path<String>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, Error> {
+    let assets = &state.assets;
+    authorize(assets, authorization)?;
+    let target = admin_path(assets, &path)?;
+    if tokio::fs::metadata(&target)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Err(HttpError::new(StatusCode::CONFLICT, "Path is a directory").into());
+    }
+
+    let existed = target.exists();
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create asset directory")?;
+    }
+    // Written aside and renamed so readers never see a half written file, each
+    // upload gets its own so concurrent ones to the same path can't interleave
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(format!(".{:016x}{}", rand::random::<u64>(), PARTIAL_SUFFIX));
+    let written = match tokio::fs::write(&partial, &body).await {
+        Ok(()) => tokio::fs::rename(&partial, &target).await,
+        Err(err) => Err(err),
+    };
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    written.context("Failed to write asset")?;
+    remove_variants(&target).await;
+    assets.forget(&target);
+    let size = body.len();
+    let hash = tokio::task::spawn_blocking(move || sha256::digest(body.as_ref()))
+        .await
+        .context("Failed to hash asset")?;
+
+    info!(?target, size, existed);
+    let status = if existed {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((
+        status,
+        Json(Uploaded {
+            path,
+            size,
+            etag: format!("\"{}\"", hash),
+        }),
+    ))
+}
+
+pub async fn remove(
+    State(state): State<Arc<router::State>>,
+    Path(path): Path<String>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, Error> {
+    let assets = &state.assets;
+    authorize(assets, authorization)?;
+    let target = admin_path(assets, &path)?;
+
+    match tokio::fs::metadata(&target).await {
+        Ok(metadata) if metadata.is_dir() => {
+            return Err(HttpError::new(StatusCode::CONFLICT, "Path is a directory").into())
+        }
+        Ok(_) => {}
+        Err(_) => return Err(HttpError::not_found("Asset not found").into()),
+    }
+
+    tokio::fs::remove_file(&target)
+        .await
+        .context("Failed to delete asset")?;
+    remove_variants(&target).await;
+    assets.forget(&target);
+
+    info!(?target, "Deleted asset");
+    Ok(StatusCode::NO_CONTENT)
+}
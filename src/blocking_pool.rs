@@ -0,0 +1,129 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::router::{Error, HttpError};
+
+#[derive(Debug, Default)]
+struct Stats {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolMetrics {
+    pub workers: usize,
+    pub max_queue: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub completed: u64,
+    pub rejected: u64,
+}
+
+// Decrements its counter however the holder goes away, including a cancelled request
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Gauge(counter)
+    }
+
+    /// Enters unless the counter is already at `max`, checked and counted in one step
+    fn try_enter(counter: &'a AtomicUsize, max: usize) -> Option<Self> {
+        let previous = counter.fetch_add(1, Ordering::Relaxed);
+        // Dropping the gauge undoes the increment when it's over the limit
+        let gauge = Gauge(counter);
+        (previous < max).then_some(gauge)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs CPU heavy jobs on Tokio's blocking threads, at most `workers` at a
+/// time, so they can't starve the async workers. Once every worker is busy,
+/// requests beyond `max_queue` waiting jobs are turned away with a 503.
+pub struct BlockingPool {
+    workers: usize,
+    max_queue: usize,
+    permits: Arc<Semaphore>,
+    stats: Arc<Stats>,
+}
+
+impl BlockingPool {
+    pub fn new(workers: usize, max_queue: usize) -> Self {
+        let workers = workers.max(1);
+        BlockingPool {
+            workers,
+            max_queue,
+            permits: Arc::new(Semaphore::new(workers)),
+            stats: Arc::default(),
+        }
+    }
+
+    pub async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        // An idle worker takes the job straight away, only jobs that have to wait
+        // count against `max_queue`
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let Some(_queued) = Gauge::try_enter(&self.stats.queued, self.max_queue) else {
+                    self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                    warn!(max_queue = self.max_queue, "Blocking pool queue is full");
+                    return Err(HttpError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Too many jobs queued, try again later",
+                    )
+                    .into());
+                };
+                self.permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .context("Blocking pool closed")?
+            }
+        };
+
+        let stats = self.stats.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            // The permit lives as long as the job, even if the request is dropped meanwhile
+            let _permit = permit;
+            let _running = Gauge::enter(&stats.running);
+            let result = job();
+            stats.completed.fetch_add(1, Ordering::Relaxed);
+            result
+        })
+        .await
+        .context("Blocking job panicked")?;
+
+        debug!(metrics = ?self.metrics());
+        result
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            workers: self.workers,
+            max_queue: self.max_queue,
+            queued: self.stats.queued.load(Ordering::Relaxed),
+            running: self.stats.running.load(Ordering::Relaxed),
+            completed: self.stats.completed.load(Ordering::Relaxed),
+            rejected: self.stats.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
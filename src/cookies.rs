@@ -0,0 +1,202 @@
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, Key, KeyInit, Nonce,
+};
+use anyhow::Context;
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::router::HttpError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNED: &str = "s";
+const ENCRYPTED: &str = "e";
+const NONCE_LEN: usize = 12;
+
+struct CookieKey {
+    id: String,
+    sign: [u8; 32],
+    encrypt: [u8; 32],
+}
+
+// Separate keys for signing and encrypting so one secret can't be used against the other
+fn derive(secret: &[u8], purpose: &str) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(purpose.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+impl CookieKey {
+    fn new(id: &str, secret: &[u8]) -> Self {
+        CookieKey {
+            id: id.to_string(),
+            sign: derive(secret, "sign"),
+            encrypt: derive(secret, "encrypt"),
+        }
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.encrypt))
+    }
+
+    fn mac(&self, message: &str) -> HmacSha256 {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&self.sign).expect("HMAC accepts any key length");
+        mac.update(message.as_bytes());
+        mac
+    }
+}
+
+/// Keys used to seal cookies. The first key issues new cookies, the rest are
+/// only accepted so cookies survive a key rotation.
+pub struct CookieKeys {
+    keys: Vec<CookieKey>,
+    /// Accept unsealed base64 cookies everywhere, not just on `/7/decode`
+    allow_legacy: bool,
+}
+
+fn tampered() -> HttpError {
+    HttpError::new(StatusCode::BAD_REQUEST, "Cookie failed verification")
+}
+
+impl CookieKeys {
+    /// Parses `id:base64secret` pairs separated by commas, newest first
+    pub fn from_config(config: &str) -> Result<Self, anyhow::Error> {
+        let keys = config
+            .split(',')
+            .map(|pair| {
+                let (id, secret) = pair
+                    .trim()
+                    .split_once(':')
+                    .with_context(|| format!("Cookie key `{}` is missing an id", pair))?;
+                let secret = rbase64::decode(secret)
+                    .with_context(|| format!("Cookie key `{}` is not base64", id))?;
+                anyhow::ensure!(secret.len() >= 32, "Cookie key `{}` is too short", id);
+                Ok(CookieKey::new(id, &secret))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(CookieKeys {
+            keys,
+            allow_legacy: false,
+        })
+    }
+
+    /// Random key for when none is configured, cookies won't survive a restart
+    pub fn generate() -> Self {
+        warn!("No cookie keys configured, generating a temporary one");
+        CookieKeys {
+            keys: vec![CookieKey::new("temp", &rand::random::<[u8; 32]>())],
+            allow_legacy: false,
+        }
+    }
+
+    pub fn with_legacy(self, allow_legacy: bool) -> Self {
+        CookieKeys {
+            allow_legacy,
+            ..self
+        }
+    }
+
+    fn current(&self) -> &CookieKey {
+        &self.keys[0]
+    }
+
+    fn find(&self, id: &str) -> Result<&CookieKey, HttpError> {
+        self.keys
+            .iter()
+            .find(|key| key.id == id)
+            .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "Cookie key is unknown"))
+    }
+
+    /// Unsealed base64, only issued when [`CookieKeys::open`] would take it back
+    pub fn plain(&self, payload: &[u8]) -> Result<String, HttpError> {
+        if !self.allow_legacy {
+            return Err(HttpError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Plain cookies are disabled",
+            ));
+        }
+        Ok(rbase64::encode(payload))
+    }
+
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let key = self.current();
+        let message = format!("{}.{}.{}", SIGNED, key.id, rbase64::encode(payload));
+        let tag = key.mac(&message).finalize().into_bytes();
+        format!("{}.{}", message, rbase64::encode(&tag))
+    }
+
+    pub fn encrypt(&self, payload: &[u8]) -> Result<String, anyhow::Error> {
+        let key = self.current();
+        let header = format!("{}.{}", ENCRYPTED, key.id);
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let sealed = key
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: payload,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::Error::msg("Failed to encrypt cookie"))?;
+
+        Ok(format!(
+            "{}.{}",
+            header,
+            rbase64::encode(&[nonce.as_slice(), sealed.as_slice()].concat())
+        ))
+    }
+
+    /// Verifies or decrypts a sealed cookie, anything else is rejected unless
+    /// legacy cookies are allowed
+    pub fn open(&self, value: &str) -> Result<Vec<u8>, HttpError> {
+        self.open_with(value, self.allow_legacy)
+    }
+
+    /// Like [`CookieKeys::open`], but also accepts the plain base64 cookies
+    /// issued before sealing existed
+    pub fn open_legacy(&self, value: &str) -> Result<Vec<u8>, HttpError> {
+        self.open_with(value, true)
+    }
+
+    fn open_with(&self, value: &str, legacy: bool) -> Result<Vec<u8>, HttpError> {
+        let parts = value.split('.').collect::<Vec<_>>();
+        debug!(?parts);
+
+        match parts.as_slice() {
+            [SIGNED, id, payload, tag] => {
+                let key = self.find(id)?;
+                let tag = rbase64::decode(tag).map_err(|_| tampered())?;
+                key.mac(&format!("{}.{}.{}", SIGNED, id, payload))
+                    .verify_slice(&tag)
+                    .map_err(|_| tampered())?;
+                rbase64::decode(payload).map_err(|_| tampered())
+            }
+            [ENCRYPTED, id, sealed] => {
+                let key = self.find(id)?;
+                let sealed = rbase64::decode(sealed).map_err(|_| tampered())?;
+                if sealed.len() < NONCE_LEN {
+                    return Err(tampered());
+                }
+                let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+                key.cipher()
+                    .decrypt(
+                        Nonce::from_slice(nonce),
+                        Payload {
+                            msg: sealed,
+                            aad: format!("{}.{}", ENCRYPTED, id).as_bytes(),
+                        },
+                    )
+                    .map_err(|_| tampered())
+            }
+            _ if legacy => rbase64::decode(value)
+                .map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, "Cookie is not base64")),
+            _ => Err(tampered()),
+        }
+    }
+}
@@ -1,14 +1,30 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::Context;
 use axum::{
-    extract::{self},
+    body::{Body, Bytes},
+    extract::{self, rejection::JsonRejection, FromRequest, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sqlx::{types, FromRow, PgPool};
 use tracing::info;
 
-use crate::router::Error;
+use crate::{
+    json_stream,
+    pagination::Pagination,
+    router::{self, Error, HttpError},
+};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone, FromRow)]
 #[serde(default)]
 pub struct Reindeer {
     name: String,
@@ -30,28 +46,88 @@ struct Contest {
     consumer: String,
 }
 
-impl From<Vec<Reindeer>> for Contest {
-    fn from(value: Vec<Reindeer>) -> Self {
-        let fastest = value
-            .iter()
-            .max_by(|rein_x, rein_y| rein_x.speed.total_cmp(&rein_y.speed))
-            .cloned()
-            .unwrap_or_default();
-        let tallest = value
-            .iter()
-            .max_by_key(|rein| rein.height)
-            .cloned()
-            .unwrap_or_default();
-        let magician = value
-            .iter()
-            .max_by_key(|rein| rein.snow_magic_power)
-            .cloned()
-            .unwrap_or_default();
-        let consumer = value
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+struct Winners {
+    fastest: String,
+    tallest: String,
+    magician: String,
+    consumer: String,
+}
+
+#[derive(Default)]
+struct Champions {
+    fastest: Option<Reindeer>,
+    tallest: Option<Reindeer>,
+    magician: Option<Reindeer>,
+    consumer: Option<Reindeer>,
+}
+
+impl Champions {
+    // Ties go to the later reindeer, same as `max_by`
+    fn update(&mut self, rein: &Reindeer) {
+        fn challenge(
+            champion: &mut Option<Reindeer>,
+            rein: &Reindeer,
+            cmp: impl Fn(&Reindeer, &Reindeer) -> Ordering,
+        ) {
+            if champion
+                .as_ref()
+                .is_none_or(|champion| cmp(rein, champion).is_ge())
+            {
+                *champion = Some(rein.clone());
+            }
+        }
+
+        challenge(&mut self.fastest, rein, |rein_x, rein_y| {
+            rein_x.speed.total_cmp(&rein_y.speed)
+        });
+        challenge(&mut self.tallest, rein, |rein_x, rein_y| {
+            rein_x.height.cmp(&rein_y.height)
+        });
+        challenge(&mut self.magician, rein, |rein_x, rein_y| {
+            rein_x.snow_magic_power.cmp(&rein_y.snow_magic_power)
+        });
+        challenge(&mut self.consumer, rein, |rein_x, rein_y| {
+            rein_x.candies.cmp(&rein_y.candies)
+        });
+    }
+}
+
+impl From<&[Reindeer]> for Champions {
+    fn from(value: &[Reindeer]) -> Self {
+        value
             .iter()
-            .max_by_key(|rein| rein.candies)
-            .cloned()
-            .unwrap_or_default();
+            .fold(Champions::default(), |mut champions, rein| {
+                champions.update(rein);
+                champions
+            })
+    }
+}
+
+impl From<&Champions> for Winners {
+    fn from(value: &Champions) -> Self {
+        let name = |champion: &Option<Reindeer>| {
+            champion
+                .as_ref()
+                .map(|rein| rein.name.clone())
+                .unwrap_or_default()
+        };
+
+        Winners {
+            fastest: name(&value.fastest),
+            tallest: name(&value.tallest),
+            magician: name(&value.magician),
+            consumer: name(&value.consumer),
+        }
+    }
+}
+
+impl From<Champions> for Contest {
+    fn from(value: Champions) -> Self {
+        let fastest = value.fastest.unwrap_or_default();
+        let tallest = value.tallest.unwrap_or_default();
+        let magician = value.magician.unwrap_or_default();
+        let consumer = value.consumer.unwrap_or_default();
 
         Contest {
             fastest: format!(
@@ -74,20 +150,885 @@ impl From<Vec<Reindeer>> for Contest {
     }
 }
 
-pub async fn task_01(
-    extract::Json(payload): extract::Json<Vec<Reindeer>>,
-) -> Result<impl IntoResponse, Error> {
-    info!(?payload);
+impl From<Vec<Reindeer>> for Contest {
+    fn from(value: Vec<Reindeer>) -> Self {
+        Champions::from(value.as_slice()).into()
+    }
+}
 
-    let result: i32 = payload.iter().map(|rein| rein.strength).sum();
+pub async fn task_01(body: Body) -> Result<impl IntoResponse, Error> {
+    let mut result = 0i64;
+    let count =
+        json_stream::for_each(body, |rein: Reindeer| result += rein.strength as i64).await?;
+    info!(count, result);
 
     Ok(result.to_string())
 }
 
-pub async fn task_02(
+pub async fn task_02(body: Body) -> Result<impl IntoResponse, Error> {
+    let mut champions = Champions::default();
+    let count = json_stream::for_each(body, |rein: Reindeer| champions.update(&rein)).await?;
+    info!(count);
+
+    Ok(Json(Contest::from(champions)))
+}
+
+async fn fetch_herd(pool: &PgPool, names: Option<&[String]>) -> Result<Vec<Reindeer>, Error> {
+    let herd = match names {
+        Some(names) => sqlx::query_as!(
+            Reindeer,
+            r#"
+            SELECT
+                name,
+                strength,
+                speed,
+                height,
+                antler_width,
+                snow_magic_power,
+                favorite_food,
+                candies
+            FROM reindeer
+            WHERE name = ANY($1)
+            ORDER BY name
+            "#,
+            names
+        )
+        .fetch_all(pool)
+        .await
+        .context("Failed to select reindeer subset")?,
+        None => sqlx::query_as!(
+            Reindeer,
+            r#"
+            SELECT
+                name,
+                strength,
+                speed,
+                height,
+                antler_width,
+                snow_magic_power,
+                favorite_food,
+                candies
+            FROM reindeer
+            ORDER BY name
+            "#
+        )
+        .fetch_all(pool)
+        .await
+        .context("Failed to select reindeer")?,
+    };
+
+    Ok(herd)
+}
+
+pub async fn registry_list(
+    pagination: Pagination,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let total = sqlx::query_scalar!("SELECT COUNT(*) FROM reindeer")
+        .fetch_one(&state.pool)
+        .await
+        .context("Failed to count reindeer")?
+        .unwrap_or(0);
+
+    let herd = sqlx::query_as!(
+        Reindeer,
+        r#"
+        SELECT
+            name,
+            strength,
+            speed,
+            height,
+            antler_width,
+            snow_magic_power,
+            favorite_food,
+            candies
+        FROM reindeer
+        ORDER BY name
+        LIMIT $1
+        OFFSET $2
+        "#,
+        pagination.limit().map(|limit| limit as i64),
+        pagination.offset() as i64
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to select reindeer")?;
+    info!(herd = herd.len(), total);
+
+    Ok(pagination.page(herd, total as usize))
+}
+
+pub async fn registry_get(
+    Path(name): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let reindeer = fetch_herd(&state.pool, Some(std::slice::from_ref(&name)))
+        .await?
+        .pop()
+        .ok_or_else(|| HttpError::not_found(format!("No reindeer named {}", name)))?;
+
+    Ok(Json(reindeer))
+}
+
+pub async fn registry_create(
+    State(state): State<Arc<router::State>>,
+    Json(reindeer): Json<Reindeer>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?reindeer);
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO reindeer (
+            name,
+            strength,
+            speed,
+            height,
+            antler_width,
+            snow_magic_power,
+            favorite_food,
+            candies
+        ) VALUES (
+            $1,
+            $2,
+            $3,
+            $4,
+            $5,
+            $6,
+            $7,
+            $8
+        )
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        reindeer.name,
+        reindeer.strength,
+        reindeer.speed,
+        reindeer.height,
+        reindeer.antler_width,
+        reindeer.snow_magic_power,
+        reindeer.favorite_food,
+        reindeer.candies
+    )
+    .execute(&state.pool)
+    .await
+    .context("Failed to insert reindeer")?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Err(HttpError::new(
+            StatusCode::CONFLICT,
+            format!("A reindeer named {} already exists", reindeer.name),
+        )
+        .into());
+    }
+
+    Ok((StatusCode::CREATED, Json(reindeer)))
+}
+
+pub async fn registry_update(
+    Path(name): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Json(reindeer): Json<Reindeer>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?name, ?reindeer);
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE reindeer SET
+            strength = $2,
+            speed = $3,
+            height = $4,
+            antler_width = $5,
+            snow_magic_power = $6,
+            favorite_food = $7,
+            candies = $8
+        WHERE name = $1
+        "#,
+        name,
+        reindeer.strength,
+        reindeer.speed,
+        reindeer.height,
+        reindeer.antler_width,
+        reindeer.snow_magic_power,
+        reindeer.favorite_food,
+        reindeer.candies
+    )
+    .execute(&state.pool)
+    .await
+    .context("Failed to update reindeer")?
+    .rows_affected();
+
+    if updated == 0 {
+        return Err(HttpError::not_found(format!("No reindeer named {}", name)).into());
+    }
+
+    Ok(Json(Reindeer { name, ..reindeer }))
+}
+
+pub async fn registry_delete(
+    Path(name): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let deleted = sqlx::query!("DELETE FROM reindeer WHERE name = $1", name)
+        .execute(&state.pool)
+        .await
+        .context("Failed to delete reindeer")?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(HttpError::not_found(format!("No reindeer named {}", name)).into());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+struct ContestRecord {
+    id: i32,
+    held_at: DateTime<Utc>,
+    participants: Vec<String>,
+    winners: types::Json<Winners>,
+    contest: types::Json<Contest>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct ContestRequest {
+    names: Option<Vec<String>>,
+}
+
+pub async fn contest_run(
+    State(state): State<Arc<router::State>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, Error> {
+    // No body runs the whole herd, but a body that is there has to be a valid request
+    let request = if body.iter().all(u8::is_ascii_whitespace) {
+        ContestRequest::default()
+    } else {
+        let mut raw = Request::new(Body::from(body));
+        *raw.headers_mut() = headers;
+        let payload: Result<Json<ContestRequest>, JsonRejection> =
+            Json::from_request(raw, &()).await;
+        let Json(request) = payload
+            .map_err(|rejection| HttpError::new(rejection.status(), rejection.body_text()))?;
+        request
+    };
+    info!(?request);
+
+    let herd = fetch_herd(&state.pool, request.names.as_deref()).await?;
+
+    if let Some(names) = &request.names {
+        let missing = names
+            .iter()
+            .filter(|name| !herd.iter().any(|rein| &rein.name == *name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(
+                HttpError::not_found(format!("No reindeer named {}", missing.join(", "))).into(),
+            );
+        }
+    }
+
+    let participants = herd
+        .iter()
+        .map(|rein| rein.name.clone())
+        .collect::<Vec<_>>();
+    let champions = Champions::from(herd.as_slice());
+    let winners = Winners::from(&champions);
+    let contest = Contest::from(champions);
+
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO contests (
+            participants,
+            winners,
+            contest
+        ) VALUES (
+            $1,
+            $2,
+            $3
+        )
+        RETURNING id, held_at
+        "#,
+        &participants,
+        serde_json::to_value(&winners).context("Failed to serialize winners")?,
+        serde_json::to_value(&contest).context("Failed to serialize contest")?
+    )
+    .fetch_one(&state.pool)
+    .await
+    .context("Failed to insert contest")?;
+
+    let record = ContestRecord {
+        id: record.id,
+        held_at: record.held_at,
+        participants,
+        winners: types::Json(winners),
+        contest: types::Json(contest),
+    };
+    info!(?record);
+
+    Ok((StatusCode::CREATED, Json(record)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct History {
+    date: Option<NaiveDate>,
+}
+
+pub async fn contest_history(
+    Query(history): Query<History>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?history);
+
+    let records = sqlx::query_as!(
+        ContestRecord,
+        r#"
+        SELECT
+            id,
+            held_at,
+            participants,
+            winners AS "winners: types::Json<Winners>",
+            contest AS "contest: types::Json<Contest>"
+        FROM contests
+        WHERE $1::DATE IS NULL OR held_at::DATE = $1
+        ORDER BY held_at
+        "#,
+        history.date
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to select contest history")?;
+
+    Ok(Json(records))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    Name,
+    Strength,
+    Speed,
+    Height,
+    AntlerWidth,
+    SnowMagicPower,
+    FavoriteFood,
+    Candies,
+}
+
+impl Field {
+    const ALL: [Field; 8] = [
+        Field::Name,
+        Field::Strength,
+        Field::Speed,
+        Field::Height,
+        Field::AntlerWidth,
+        Field::SnowMagicPower,
+        Field::FavoriteFood,
+        Field::Candies,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Strength => "strength",
+            Field::Speed => "speed",
+            Field::Height => "height",
+            Field::AntlerWidth => "antler_width",
+            Field::SnowMagicPower => "snow_magic_power",
+            Field::FavoriteFood => "favorite_food",
+            Field::Candies => "candies",
+        }
+    }
+
+    fn number(&self, rein: &Reindeer) -> Option<f64> {
+        match self {
+            Field::Name | Field::FavoriteFood => None,
+            Field::Strength => Some(rein.strength as f64),
+            Field::Speed => Some(rein.speed as f64),
+            Field::Height => Some(rein.height as f64),
+            Field::AntlerWidth => Some(rein.antler_width as f64),
+            Field::SnowMagicPower => Some(rein.snow_magic_power as f64),
+            Field::Candies => Some(rein.candies as f64),
+        }
+    }
+
+    fn text(&self, rein: &Reindeer) -> String {
+        match self {
+            Field::Name => rein.name.clone(),
+            Field::FavoriteFood => rein.favorite_food.clone(),
+            field => field.number(rein).unwrap_or_default().to_string(),
+        }
+    }
+
+    fn compare(&self, a: &Reindeer, b: &Reindeer) -> Ordering {
+        match (self.number(a), self.number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => self.text(a).cmp(&self.text(b)),
+        }
+    }
+
+    fn numeric(&self) -> Result<Field, HttpError> {
+        match self {
+            Field::Name | Field::FavoriteFood => Err(HttpError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("{} is not a numeric field", self.as_str()),
+            )),
+            field => Ok(*field),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "aggregation", rename_all = "snake_case")]
+enum Aggregation {
+    /// Highest value wins
+    Max { field: Field },
+    /// Lowest value wins
+    Min { field: Field },
+    /// Closest to the herd average wins
+    Average { field: Field },
+    /// Highest weighted sum of the given fields wins
+    Weighted { weights: HashMap<Field, f64> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TieBreak {
+    by: Field,
+    #[serde(default)]
+    order: Direction,
+}
+
+fn default_template() -> String {
+    "{name} scored {score}".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Category {
+    name: String,
+    #[serde(flatten)]
+    aggregation: Aggregation,
+    #[serde(default)]
+    tie_break: Vec<TieBreak>,
+    #[serde(default = "default_template")]
+    template: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContestSpec {
+    categories: Vec<Category>,
+}
+
+#[derive(Debug, Serialize)]
+struct Ranking {
+    rank: usize,
+    name: String,
+    score: f64,
+    summary: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CategoryResult {
+    category: String,
+    winner: Option<String>,
+    rankings: Vec<Ranking>,
+}
+
+impl Category {
+    fn check(&self) -> Result<(), HttpError> {
+        match &self.aggregation {
+            Aggregation::Max { field }
+            | Aggregation::Min { field }
+            | Aggregation::Average { field } => field.numeric().map(|_| ()),
+            Aggregation::Weighted { weights } if weights.is_empty() => Err(HttpError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("{} has no weights", self.name),
+            )),
+            Aggregation::Weighted { weights } => weights
+                .keys()
+                .try_for_each(|field| field.numeric().map(|_| ())),
+        }
+    }
+
+    fn scores(&self, herd: &[Reindeer]) -> Vec<f64> {
+        let value = |field: &Field, rein: &Reindeer| field.number(rein).unwrap_or_default();
+
+        match &self.aggregation {
+            Aggregation::Max { field } | Aggregation::Min { field } => {
+                herd.iter().map(|rein| value(field, rein)).collect()
+            }
+            Aggregation::Average { field } => {
+                let average = herd.iter().map(|rein| value(field, rein)).sum::<f64>()
+                    / herd.len().max(1) as f64;
+                herd.iter()
+                    .map(|rein| (value(field, rein) - average).abs())
+                    .collect()
+            }
+            Aggregation::Weighted { weights } => herd
+                .iter()
+                .map(|rein| {
+                    weights
+                        .iter()
+                        .map(|(field, weight)| weight * value(field, rein))
+                        .sum()
+                })
+                .collect(),
+        }
+    }
+
+    fn render(&self, rein: &Reindeer, rank: usize, score: f64) -> String {
+        Field::ALL
+            .iter()
+            .fold(self.template.clone(), |summary, field| {
+                summary.replace(&format!("{{{}}}", field.as_str()), &field.text(rein))
+            })
+            .replace("{score}", &score.to_string())
+            .replace("{rank}", &rank.to_string())
+    }
+
+    fn rank(&self, herd: &[Reindeer]) -> CategoryResult {
+        let descending = matches!(
+            self.aggregation,
+            Aggregation::Max { .. } | Aggregation::Weighted { .. }
+        );
+
+        let mut scored = herd.iter().zip(self.scores(herd)).collect::<Vec<_>>();
+        scored.sort_by(|(rein_x, score_x), (rein_y, score_y)| {
+            let by_score = if descending {
+                score_y.total_cmp(score_x)
+            } else {
+                score_x.total_cmp(score_y)
+            };
+
+            self.tie_break
+                .iter()
+                .fold(by_score, |ordering, tie| {
+                    ordering.then_with(|| match tie.order {
+                        Direction::Asc => tie.by.compare(rein_x, rein_y),
+                        Direction::Desc => tie.by.compare(rein_y, rein_x),
+                    })
+                })
+                .then_with(|| rein_x.name.cmp(&rein_y.name))
+        });
+
+        let rankings = scored
+            .into_iter()
+            .enumerate()
+            .map(|(index, (rein, score))| Ranking {
+                rank: index + 1,
+                name: rein.name.clone(),
+                score,
+                summary: self.render(rein, index + 1, score),
+            })
+            .collect::<Vec<_>>();
+
+        CategoryResult {
+            category: self.name.clone(),
+            winner: rankings.first().map(|ranking| ranking.summary.clone()),
+            rankings,
+        }
+    }
+}
+
+impl ContestSpec {
+    fn check(&self) -> Result<(), HttpError> {
+        self.categories.iter().try_for_each(Category::check)
+    }
+
+    fn run(&self, herd: &[Reindeer]) -> Vec<CategoryResult> {
+        self.categories
+            .iter()
+            .map(|category| category.rank(herd))
+            .collect()
+    }
+}
+
+async fn fetch_spec(pool: &PgPool, name: &str) -> Result<ContestSpec, Error> {
+    let spec = sqlx::query_scalar!(
+        r#"SELECT spec AS "spec: types::Json<ContestSpec>" FROM contest_specs WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to select contest spec")?
+    .ok_or_else(|| HttpError::not_found(format!("No contest spec named {}", name)))?;
+
+    Ok(spec.0)
+}
+
+pub async fn spec_list(
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let names = sqlx::query_scalar!("SELECT name FROM contest_specs ORDER BY name")
+        .fetch_all(&state.pool)
+        .await
+        .context("Failed to select contest specs")?;
+
+    Ok(Json(names))
+}
+
+pub async fn spec_get(
+    Path(name): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    Ok(Json(fetch_spec(&state.pool, &name).await?))
+}
+
+pub async fn spec_put(
+    Path(name): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Json(spec): Json<ContestSpec>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?name, ?spec);
+    spec.check()?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO contest_specs (
+            name,
+            spec
+        ) VALUES (
+            $1,
+            $2
+        )
+        ON CONFLICT (name) DO UPDATE SET spec = EXCLUDED.spec
+        "#,
+        name,
+        serde_json::to_value(&spec).context("Failed to serialize contest spec")?
+    )
+    .execute(&state.pool)
+    .await
+    .context("Failed to upsert contest spec")?;
+
+    Ok(Json(spec))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CustomContest {
+    spec: Option<ContestSpec>,
+    spec_name: Option<String>,
+    reindeer: Option<Vec<Reindeer>>,
+}
+
+pub async fn contest_custom(
+    State(state): State<Arc<router::State>>,
+    Json(request): Json<CustomContest>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?request);
+
+    let spec = match (request.spec, request.spec_name) {
+        (Some(spec), _) => spec,
+        (None, Some(name)) => fetch_spec(&state.pool, &name).await?,
+        (None, None) => {
+            return Err(HttpError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Either spec or spec_name is required",
+            )
+            .into())
+        }
+    };
+    spec.check()?;
+
+    let herd = match request.reindeer {
+        Some(herd) => herd,
+        None => fetch_herd(&state.pool, None).await?,
+    };
+
+    let results = spec.run(&herd);
+    info!(?results);
+
+    Ok(Json(results))
+}
+
+#[derive(Debug, Serialize)]
+struct Bin {
+    start: f64,
+    end: f64,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct FieldStats {
+    mean: f64,
+    median: f64,
+    std_dev: f64,
+    min: f64,
+    max: f64,
+    percentiles: BTreeMap<String, f64>,
+    histogram: Vec<Bin>,
+}
+
+#[derive(Debug, Serialize)]
+struct HerdStats {
+    count: usize,
+    fields: BTreeMap<&'static str, FieldStats>,
+    correlations: BTreeMap<String, Option<f64>>,
+}
+
+const PERCENTILES: [f64; 5] = [10f64, 25f64, 50f64, 75f64, 90f64];
+const DEFAULT_BINS: usize = 10;
+// Each bin is a pass over the herd
+const MAX_BINS: usize = 256;
+
+// Linear interpolation between the closest ranks, expects sorted values
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0f64;
+    }
+    let rank = percent / 100f64 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+fn std_dev(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    (values.iter().map(|val| (val - mean).powi(2)).sum::<f64>() / values.len().max(1) as f64).sqrt()
+}
+
+fn histogram(sorted: &[f64], bins: usize) -> Vec<Bin> {
+    let (Some(min), Some(max)) = (sorted.first(), sorted.last()) else {
+        return Vec::new();
+    };
+    let bins = if min == max { 1 } else { bins };
+    let width = (max - min) / bins as f64;
+
+    (0..bins)
+        .map(|bin| {
+            let start = min + width * bin as f64;
+            let end = if bin == bins - 1 { *max } else { start + width };
+            let count = sorted
+                .iter()
+                .filter(|val| **val >= start && (**val < end || bin == bins - 1))
+                .count();
+            Bin { start, end, count }
+        })
+        .collect()
+}
+
+// Pearson correlation coefficient, None when either field has no variance
+fn correlation(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let (mean_x, mean_y) = (mean(xs), mean(ys));
+    let covariance = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let spread_x = xs.iter().map(|x| (x - mean_x).powi(2)).sum::<f64>().sqrt();
+    let spread_y = ys.iter().map(|y| (y - mean_y).powi(2)).sum::<f64>().sqrt();
+
+    if spread_x == 0f64 || spread_y == 0f64 {
+        None
+    } else {
+        Some(covariance / (spread_x * spread_y))
+    }
+}
+
+impl HerdStats {
+    fn new(herd: &[&Reindeer], bins: usize) -> Self {
+        let columns = Field::ALL
+            .iter()
+            .filter(|field| field.numeric().is_ok())
+            .map(|field| {
+                let values = herd
+                    .iter()
+                    .map(|rein| field.number(rein).unwrap_or_default())
+                    .collect::<Vec<_>>();
+                (field.as_str(), values)
+            })
+            .collect::<Vec<_>>();
+
+        let fields = columns
+            .iter()
+            .map(|(name, values)| {
+                let mut sorted = values.clone();
+                sorted.sort_by(f64::total_cmp);
+
+                let stats = FieldStats {
+                    mean: mean(&sorted),
+                    median: percentile(&sorted, 50f64),
+                    std_dev: std_dev(&sorted),
+                    min: sorted.first().copied().unwrap_or_default(),
+                    max: sorted.last().copied().unwrap_or_default(),
+                    percentiles: PERCENTILES
+                        .iter()
+                        .map(|percent| (format!("p{}", percent), percentile(&sorted, *percent)))
+                        .collect(),
+                    histogram: histogram(&sorted, bins),
+                };
+                (*name, stats)
+            })
+            .collect();
+
+        let correlations = columns
+            .iter()
+            .tuple_combinations()
+            .map(|((name_x, xs), (name_y, ys))| {
+                (format!("{}:{}", name_x, name_y), correlation(xs, ys))
+            })
+            .collect();
+
+        HerdStats {
+            count: herd.len(),
+            fields,
+            correlations,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    FavoriteFood,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    group_by: Option<GroupBy>,
+    bins: Option<usize>,
+}
+
+pub async fn stats(
+    Query(query): Query<StatsQuery>,
     extract::Json(payload): extract::Json<Vec<Reindeer>>,
 ) -> Result<impl IntoResponse, Error> {
-    info!(?payload);
+    info!(?query, herd = payload.len());
+
+    let bins = query.bins.unwrap_or(DEFAULT_BINS);
+    if !(1..=MAX_BINS).contains(&bins) {
+        return Err(HttpError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("bins must be between 1 and {}", MAX_BINS),
+        )
+        .into());
+    }
 
-    Ok(Json(Contest::from(payload)))
+    match query.group_by {
+        Some(GroupBy::FavoriteFood) => {
+            let groups = payload
+                .iter()
+                .into_group_map_by(|rein| rein.favorite_food.clone())
+                .into_iter()
+                .map(|(food, herd)| (food, HerdStats::new(&herd, bins)))
+                .collect::<BTreeMap<_, _>>();
+            Ok(Json(groups).into_response())
+        }
+        None => {
+            let herd = payload.iter().collect::<Vec<_>>();
+            Ok(Json(HerdStats::new(&herd, bins)).into_response())
+        }
+    }
 }
@@ -1,29 +1,244 @@
-use axum::{extract::Query, response::IntoResponse, Json};
+use std::{cmp::Ordering, collections::HashSet};
+
+use axum::{response::IntoResponse, Json};
+use axum_extra::extract::Query;
+use regex::Regex;
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::{
+    pagination::Pagination,
+    router::Error,
+    validation::{FieldError, ValidationFailed},
+};
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct Shape {
+    /// `value` sorts by the items themselves, anything else is a JSON pointer
+    sort: Option<String>,
+    order: SortOrder,
+    /// `<pointer>:<eq|contains|matches>:<operand>`, all filters must match
+    filter: Vec<String>,
+    dedup: bool,
+    /// Comma separated JSON pointers kept on object items
+    fields: Option<String>,
+}
+
+enum Filter {
+    Eq(String, Value),
+    Contains(String, Value),
+    Matches(String, Regex),
+}
+
+// Accepts `name` as shorthand for `/name`, the empty pointer is the item itself
+fn pointer(raw: &str) -> String {
+    if raw.is_empty() || raw.starts_with('/') {
+        raw.to_string()
+    } else {
+        format!("/{}", raw)
+    }
+}
+
+fn lookup<'a>(item: &'a Value, pointer: &str) -> &'a Value {
+    item.pointer(pointer).unwrap_or(&Value::Null)
+}
+
+// Operands are JSON when they parse as JSON, so `eq:5` and `eq:"5"` differ
+fn operand(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(val) => val.clone(),
+        val => val.to_string(),
+    }
+}
+
+impl Filter {
+    fn parse(raw: &str) -> Result<Filter, String> {
+        let mut parts = raw.splitn(3, ':');
+        let (Some(pointer), Some(op), Some(val)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("must look like <pointer>:<op>:<value>".to_string());
+        };
+        let pointer = self::pointer(pointer);
+
+        match op {
+            "eq" => Ok(Filter::Eq(pointer, operand(val))),
+            "contains" => Ok(Filter::Contains(pointer, operand(val))),
+            "matches" => Regex::new(val)
+                .map(|regex| Filter::Matches(pointer, regex))
+                .map_err(|err| err.to_string()),
+            op => Err(format!("unknown operator {}", op)),
+        }
+    }
+
+    fn test(&self, item: &Value) -> bool {
+        match self {
+            Filter::Eq(pointer, val) => lookup(item, pointer) == val,
+            Filter::Contains(pointer, val) => match lookup(item, pointer) {
+                Value::String(found) => found.contains(&text(val)),
+                Value::Array(found) => found.contains(val),
+                Value::Object(found) => found.contains_key(&text(val)),
+                _ => false,
+            },
+            Filter::Matches(pointer, regex) => regex.is_match(&text(lookup(item, pointer))),
+        }
+    }
+}
+
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
 
-use crate::router::Error;
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (a, b) if rank(a) == rank(b) => a.to_string().cmp(&b.to_string()),
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
 
-#[derive(Deserialize, Debug)]
-pub struct Pagination {
-    offset: Option<usize>,
-    limit: Option<usize>,
-    split: Option<usize>,
+fn project(item: Value, fields: &[String]) -> Value {
+    match item {
+        Value::Object(_) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|field| {
+                    item.pointer(field)
+                        .map(|val| (field.trim_start_matches('/').to_string(), val.clone()))
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        item => item,
+    }
 }
+
 pub async fn task_00(
-    pagination: Query<Pagination>,
-    Json(payload): Json<Vec<String>>,
+    pagination: Pagination,
+    Query(shape): Query<Shape>,
+    Json(payload): Json<Vec<Value>>,
 ) -> Result<impl IntoResponse, Error> {
-    let size = payload.len();
-    let result = payload
+    info!(?shape);
+
+    let mut errors = Vec::new();
+    let filters = shape
+        .filter
+        .iter()
+        .enumerate()
+        .filter_map(|(index, raw)| {
+            Filter::parse(raw)
+                .map_err(|message| errors.push(FieldError::new(index, "filter", message)))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(ValidationFailed { errors }.into());
+    }
+
+    let mut seen = HashSet::new();
+    let mut items = payload
         .into_iter()
-        .skip(pagination.offset.unwrap_or_default())
-        .take(pagination.limit.unwrap_or(size))
+        .filter(|item| filters.iter().all(|filter| filter.test(item)))
+        .filter(|item| !shape.dedup || seen.insert(item.to_string()))
         .collect::<Vec<_>>();
 
-    if let Some(split) = pagination.split {
-        let result = result.chunks(split).collect::<Vec<_>>();
-        Ok(Json(result).into_response())
-    } else {
-        Ok(Json(result).into_response())
+    if let Some(sort) = &shape.sort {
+        let pointer = if sort == "value" {
+            String::new()
+        } else {
+            pointer(sort)
+        };
+        items.sort_by(|a, b| {
+            let ordering = compare(lookup(a, &pointer), lookup(b, &pointer));
+            match shape.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+    }
+
+    if let Some(fields) = &shape.fields {
+        let fields = fields.split(',').map(pointer).collect::<Vec<_>>();
+        items = items
+            .into_iter()
+            .map(|item| project(item, &fields))
+            .collect();
+    }
+
+    Ok(pagination.slice(items))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn matches(filter: &str, item: Value) -> bool {
+        Filter::parse(filter).unwrap().test(&item)
+    }
+
+    #[test]
+    fn parses_operators_and_rejects_malformed_filters() {
+        assert!(Filter::parse("name:eq:Rudolph").is_ok());
+        assert!(Filter::parse("/tags:contains:red").is_ok());
+        assert!(Filter::parse(":matches:^R").is_ok());
+        // Only the first two colons split, the rest belong to the operand
+        assert!(matches("time:eq:12:30", json!({ "time": "12:30" })));
+
+        assert!(Filter::parse("name:eq").is_err());
+        assert!(Filter::parse("name").is_err());
+        assert!(Filter::parse("name:like:R").is_err());
+        assert!(Filter::parse("name:matches:(").is_err());
+    }
+
+    #[test]
+    fn eq_compares_operands_by_type() {
+        assert!(matches("n:eq:5", json!({ "n": 5 })));
+        assert!(!matches("n:eq:5", json!({ "n": "5" })));
+        assert!(matches("n:eq:\"5\"", json!({ "n": "5" })));
+        assert!(!matches("n:eq:true", json!({ "n": "true" })));
+        assert!(matches("n:eq:true", json!({ "n": true })));
+        // Operands that aren't JSON are plain strings
+        assert!(matches("name:eq:Rudolph", json!({ "name": "Rudolph" })));
+        assert!(matches("missing:eq:null", json!({})));
+    }
+
+    #[test]
+    fn contains_and_matches() {
+        assert!(matches("name:contains:dol", json!({ "name": "Rudolph" })));
+        assert!(matches("tags:contains:3", json!({ "tags": [1, 3] })));
+        assert!(!matches("tags:contains:3", json!({ "tags": ["3"] })));
+        assert!(matches(
+            "meta:contains:nose",
+            json!({ "meta": { "nose": "red" } })
+        ));
+        assert!(!matches("n:contains:1", json!({ "n": 10 })));
+        assert!(matches("n:matches:^1[0-9]$", json!({ "n": 12 })));
+        assert!(matches(":matches:^R", json!("Rudolph")));
     }
 }
@@ -1,8 +1,14 @@
-use axum::{response::IntoResponse, Json};
+use std::collections::{BTreeMap, HashSet};
+
+use aho_corasick::AhoCorasick;
+use anyhow::Context;
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, Json};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use tracing::info;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::router::Error;
+use crate::router::{Error, HttpError};
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 struct ElfCount {
@@ -13,27 +19,282 @@ struct ElfCount {
     no_elf_shelfs: usize,
 }
 
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+struct CountOptions {
+    /// Only ASCII letters are folded
+    ignore_case: bool,
+    overlapping: bool,
+    whole_word: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+struct PhraseMatch {
+    phrase: usize,
+    start: usize,
+    end: usize,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn count_phrases(
+    text: &str,
+    phrases: &[String],
+    options: CountOptions,
+) -> Result<Vec<PhraseMatch>, Error> {
+    let automaton = AhoCorasick::builder()
+        .ascii_case_insensitive(options.ignore_case)
+        .build(phrases)
+        .context("Failed to build phrase automaton")?;
+
+    // Overlapping search finds every candidate so whole word filtering can't
+    // hide a later valid match behind an invalid one
+    let mut matches = automaton
+        .find_overlapping_iter(text)
+        .map(|found| PhraseMatch {
+            phrase: found.pattern().as_usize(),
+            start: found.start(),
+            end: found.end(),
+        })
+        .filter(|found| {
+            !options.whole_word
+                || (!text[..found.start].chars().next_back().is_some_and(is_word)
+                    && !text[found.end..].chars().next().is_some_and(is_word))
+        })
+        .collect::<Vec<_>>();
+
+    if !options.overlapping {
+        matches.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        let mut last_end = 0;
+        matches.retain(|found| {
+            let keep = found.start >= last_end;
+            if keep {
+                last_end = found.end;
+            }
+            keep
+        });
+    }
+
+    Ok(matches)
+}
+
+const ELF: &str = "elf";
 const ELF_ON_A_SHELF: &str = "elf on a shelf";
+const SHELF: &str = "shelf";
 pub async fn task_00(body: String) -> Result<impl IntoResponse, Error> {
-    // let body = body.to_lowercase();
     info!(?body);
-    let elf = body.matches("elf").count();
-    let elf_shelfs = body
-        .chars()
-        .map_windows(|w: &[_; ELF_ON_A_SHELF.len()]| {
-            if String::from_iter(w) == *ELF_ON_A_SHELF {
-                1
-            } else {
-                0
-            }
-        })
-        .sum();
-    let no_elf_shelfs = body.matches("shelf").count() - elf_shelfs;
+    let phrases = [ELF, ELF_ON_A_SHELF, SHELF].map(String::from);
+    let options = CountOptions {
+        ignore_case: false,
+        overlapping: true,
+        whole_word: false,
+    };
+
+    let mut counts = [0usize; 3];
+    for found in count_phrases(&body, &phrases, options)? {
+        counts[found.phrase] += 1;
+    }
+    let [elf, elf_shelfs, shelfs] = counts;
+
     let elf_count = ElfCount {
         elf,
         elf_shelfs,
-        no_elf_shelfs,
+        no_elf_shelfs: shelfs - elf_shelfs,
     };
     info!(?elf_count);
     Ok(Json(elf_count))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct CountRequest {
+    text: String,
+    phrases: Vec<String>,
+    #[serde(flatten)]
+    options: CountOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct Located {
+    phrase: String,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct CountResponse {
+    counts: BTreeMap<String, usize>,
+    matches: Vec<Located>,
+}
+
+pub async fn count(Json(request): Json<CountRequest>) -> Result<impl IntoResponse, Error> {
+    info!(phrases = ?request.phrases, options = ?request.options);
+
+    if request.phrases.iter().any(String::is_empty) {
+        return Err(HttpError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Phrases must not be empty",
+        )
+        .into());
+    }
+
+    let phrases = request.phrases.into_iter().unique().collect::<Vec<_>>();
+    let matches = count_phrases(&request.text, &phrases, request.options)?;
+
+    let mut counts = phrases
+        .iter()
+        .map(|phrase| (phrase.clone(), 0))
+        .collect::<BTreeMap<_, _>>();
+    let matches = matches
+        .into_iter()
+        .map(|found| {
+            let phrase = phrases[found.phrase].clone();
+            *counts.entry(phrase.clone()).or_default() += 1;
+            Located {
+                phrase,
+                start: found.start,
+                end: found.end,
+            }
+        })
+        .collect();
+
+    Ok(Json(CountResponse { counts, matches }))
+}
+
+const ENGLISH_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "he",
+    "her", "his", "i", "in", "is", "it", "its", "of", "on", "or", "she", "so", "that", "the",
+    "their", "there", "they", "this", "to", "was", "we", "were", "with", "you",
+];
+const DEFAULT_TOP_K: usize = 10;
+const DEFAULT_NGRAM: usize = 2;
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyzeQuery {
+    top_k: Option<usize>,
+    ngram: Option<usize>,
+    /// `english` for the built in list, otherwise comma separated words
+    stopwords: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Term {
+    term: String,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Readability {
+    flesch_reading_ease: f64,
+    flesch_kincaid_grade: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Analysis {
+    characters: usize,
+    tokens: usize,
+    unique_tokens: usize,
+    sentences: usize,
+    syllables: usize,
+    top_words: Vec<Term>,
+    top_ngrams: Vec<Term>,
+    readability: Option<Readability>,
+}
+
+// Vowel groups with a silent trailing e, good enough for English readability
+fn syllables(word: &str) -> usize {
+    let vowel = |c: char| "aeiouy".contains(c);
+    let groups = word
+        .chars()
+        .map(vowel)
+        .dedup()
+        .filter(|is_vowel| *is_vowel)
+        .count();
+    // Only a lone trailing e is silent, `cookie` keeps its `ie`
+    let mut tail = word.chars().rev();
+    let silent = tail.next() == Some('e')
+        && tail.next().is_some_and(|c| !vowel(c) && c != 'l')
+        && groups > 1;
+    (groups - usize::from(silent)).max(1)
+}
+
+fn top_terms(terms: impl Iterator<Item = String>, top_k: usize) -> Vec<Term> {
+    terms
+        .counts()
+        .into_iter()
+        .map(|(term, count)| Term { term, count })
+        .sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)))
+        .take(top_k)
+        .collect()
+}
+
+pub async fn analyze(
+    Query(query): Query<AnalyzeQuery>,
+    body: String,
+) -> Result<impl IntoResponse, Error> {
+    info!(?query);
+
+    let top_k = query.top_k.unwrap_or(DEFAULT_TOP_K);
+    let ngram = query.ngram.unwrap_or(DEFAULT_NGRAM);
+    if ngram == 0 {
+        return Err(
+            HttpError::new(StatusCode::UNPROCESSABLE_ENTITY, "ngram must be positive").into(),
+        );
+    }
+
+    let stopwords = match query.stopwords.as_deref() {
+        None => HashSet::new(),
+        Some("english") => ENGLISH_STOPWORDS
+            .iter()
+            .map(|word| word.to_string())
+            .collect(),
+        Some(words) => words
+            .split(',')
+            .map(|word| word.trim().to_lowercase())
+            .collect(),
+    };
+
+    let tokens = body
+        .unicode_words()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+    let sentences = body
+        .unicode_sentences()
+        .filter(|sentence| sentence.unicode_words().next().is_some())
+        .count();
+    let syllables = tokens.iter().map(|word| syllables(word)).sum::<usize>();
+
+    let kept = tokens
+        .iter()
+        .filter(|word| !stopwords.contains(*word))
+        .collect::<Vec<_>>();
+    let top_words = top_terms(kept.iter().map(|word| word.to_string()), top_k);
+    let top_ngrams = top_terms(
+        kept.windows(ngram).map(|window| window.iter().join(" ")),
+        top_k,
+    );
+
+    let readability = (!tokens.is_empty() && sentences > 0).then(|| {
+        let words_per_sentence = tokens.len() as f64 / sentences as f64;
+        let syllables_per_word = syllables as f64 / tokens.len() as f64;
+        Readability {
+            flesch_reading_ease: 206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word,
+            flesch_kincaid_grade: 0.39 * words_per_sentence + 11.8 * syllables_per_word - 15.59,
+        }
+    });
+
+    let analysis = Analysis {
+        characters: body.chars().count(),
+        tokens: tokens.len(),
+        unique_tokens: tokens.iter().unique().count(),
+        sentences,
+        syllables,
+        top_words,
+        top_ngrams,
+        readability,
+    };
+    info!(?analysis);
+
+    Ok(Json(analysis))
+}
@@ -1,99 +1,862 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use anyhow::Context;
-use axum::{http::HeaderMap, response::IntoResponse, Json};
-use serde::{Deserialize, Serialize};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::{types, FromRow, PgPool, Postgres, Transaction};
 use tracing::{debug, info};
 
-use crate::router::Error;
+use crate::{
+    cookies::CookieKeys,
+    pagination::Pagination,
+    router::{self, Error, HttpError},
+};
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default)]
 #[serde(default)]
 struct BakeInput {
-    recipe: HashMap<String, u64>,
-    pantry: HashMap<String, u64>,
+    recipe: Pantry,
+    pantry: Pantry,
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 struct BakeOutput {
     cookies: u64,
-    pantry: HashMap<String, u64>,
-}
-
-const COOKIE_HEADER: &str = "Cookie";
-const RECIPE: usize = "recipe=".len();
-
-pub async fn task_01(headers: HeaderMap) -> Result<impl IntoResponse, Error> {
-    let cookie = headers
-        .get(COOKIE_HEADER)
-        .context("Heading not found")?
-        .to_str()
-        .context("Heading not string")?;
-    info!(?cookie);
-    let recipe = String::from_utf8(
-        rbase64::decode(&cookie[RECIPE..])
-            .with_context(|| format!("Unable to decode cookie: {}", cookie))?,
-    )
-    .context("Unable to convert decoded value to string")?;
+    pantry: Pantry,
+    /// Ingredient that ran out first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limiting: Option<String>,
+    /// Recipe ingredients the pantry doesn't have at all
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    missing: Vec<String>,
+}
+
+const RECIPE_COOKIE: &str = "recipe";
+
+fn recipe(jar: &CookieJar, keys: &CookieKeys, legacy: bool) -> Result<String, Error> {
+    let cookie = jar
+        .get(RECIPE_COOKIE)
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "Recipe cookie not found"))?;
+    debug!(?cookie);
+
+    let payload = if legacy {
+        keys.open_legacy(cookie.value())?
+    } else {
+        keys.open(cookie.value())?
+    };
+    let recipe = String::from_utf8(payload)
+        .map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, "Recipe is not UTF-8"))?;
+    Ok(recipe)
+}
+
+pub async fn task_01(
+    State(state): State<Arc<router::State>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, Error> {
+    // Only echoes the cookie back, so it keeps accepting unsealed ones
+    let recipe = recipe(&jar, &state.cookie_keys, true)?;
 
     info!(?recipe);
     Ok(recipe)
 }
 
-pub async fn task_02(headers: HeaderMap) -> Result<impl IntoResponse, Error> {
-    let cookie = headers
-        .get(COOKIE_HEADER)
-        .context("Heading not found")?
-        .to_str()
-        .context("Heading not string")?;
-    debug!(?cookie);
-    let base64 = &cookie[RECIPE..];
-    debug!(?base64);
-    let decoded = &String::from_utf8(
-        rbase64::decode(base64).with_context(|| format!("Unable to decode cookie: {}", cookie))?,
-    )
-    .context("Unable to convert decoded value to string")?;
+pub async fn task_02(
+    State(state): State<Arc<router::State>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, Error> {
+    let output = bake_cookie(&jar, &state.cookie_keys)?;
+
+    info!(?output);
+    Ok(Json(output))
+}
+
+fn bake_cookie(jar: &CookieJar, keys: &CookieKeys) -> Result<BakeOutput, Error> {
+    let decoded = &recipe(jar, keys, false)?;
 
     debug!(?decoded);
-    let recipe: BakeInput =
-        serde_json::from_str(decoded).context("Unable to parse decoded to json")?;
+    let recipe: BakeInput = serde_json::from_str(decoded)
+        .map_err(|err| HttpError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
     info!(?decoded);
 
-    let max_cookies = recipe
-        .recipe
+    Ok(bake(&recipe.recipe, recipe.pantry, None)?)
+}
+
+/// Exact ingredient amount, whole amounts serialize as integers so existing
+/// clients keep seeing the same numbers
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(transparent)]
+struct Amount(Decimal);
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self.0.fract().is_zero(), self.0.to_u64(), self.0.to_i64()) {
+            (true, Some(whole), _) => serializer.serialize_u64(whole),
+            (true, None, Some(whole)) => serializer.serialize_i64(whole),
+            _ => serializer.serialize_f64(self.0.to_f64().unwrap_or_default()),
+        }
+    }
+}
+
+type Pantry = HashMap<String, Amount>;
+
+fn overflow(ingredient: &str) -> HttpError {
+    unprocessable(format!("Amount of {} is out of range", ingredient))
+}
+
+// Bakes as many cookies as the pantry allows, capped at `limit` when given
+fn bake(recipe: &Pantry, pantry: Pantry, limit: Option<u64>) -> Result<BakeOutput, HttpError> {
+    if let Some((name, _)) = recipe
         .iter()
-        .filter_map(|(key, val)| {
-            if val > &0 {
-                Some(
-                    recipe
-                        .pantry
-                        .get(key)
-                        .map(|pantry| pantry / val)
-                        .unwrap_or(0),
-                )
-            } else {
-                None
-            }
+        .chain(&pantry)
+        .find(|(_, amount)| amount.0.is_sign_negative() && !amount.0.is_zero())
+    {
+        return Err(unprocessable(format!("{} must not be negative", name)));
+    }
+
+    let needed = recipe
+        .iter()
+        .filter(|(_, amount)| !amount.0.is_zero())
+        .sorted_by_key(|(name, _)| *name)
+        .collect::<Vec<_>>();
+
+    let missing = needed
+        .iter()
+        .filter(|(name, _)| !pantry.contains_key(*name))
+        .map(|(name, _)| name.to_string())
+        .collect::<Vec<_>>();
+
+    let batches = needed
+        .iter()
+        .map(|(name, amount)| {
+            let have = pantry.get(*name).copied().unwrap_or_default();
+            have.0
+                .checked_div(amount.0)
+                .map(|batches| (batches.floor(), name.to_string()))
+                .ok_or_else(|| overflow(name))
         })
-        .min()
-        .unwrap_or(0);
-    info!(?max_cookies);
+        .collect::<Result<Vec<_>, _>>()?;
+    let limiting = batches.into_iter().min();
+
+    let max_cookies = match &limiting {
+        Some((batches, name)) => batches.to_u64().ok_or_else(|| overflow(name))?,
+        None => 0,
+    };
+    let max_cookies = limit.map_or(max_cookies, |limit| limit.min(max_cookies));
+    info!(?max_cookies, ?limiting, ?missing);
 
-    let pantry = recipe
-        .pantry
+    let pantry = pantry
         .into_iter()
-        .map(|(key, val)| {
-            recipe
-                .recipe
-                .get(&key)
-                .map(|rec| (key.clone(), val - (rec * max_cookies)))
-                .unwrap_or((key, val))
+        .map(|(key, val)| match recipe.get(&key) {
+            Some(rec) => rec
+                .0
+                .checked_mul(Decimal::from(max_cookies))
+                .and_then(|used| val.0.checked_sub(used))
+                .map(|left| (key.clone(), Amount(left)))
+                .ok_or_else(|| overflow(&key)),
+            None => Ok((key, val)),
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
-    info!(?pantry);
-    Ok(Json(BakeOutput {
+    Ok(BakeOutput {
         cookies: max_cookies,
         pantry,
-    }))
+        limiting: limiting.map(|(_, name)| name),
+        missing,
+    })
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Seal {
+    Plain,
+    #[default]
+    Signed,
+    Encrypted,
+}
+
+impl Seal {
+    fn apply(self, keys: &CookieKeys, payload: &[u8]) -> Result<String, Error> {
+        Ok(match self {
+            Seal::Plain => keys.plain(payload)?,
+            Seal::Signed => keys.sign(payload),
+            Seal::Encrypted => keys.encrypt(payload)?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueQuery {
+    #[serde(default)]
+    seal: Seal,
+}
+
+pub async fn issue(
+    Query(query): Query<IssueQuery>,
+    State(state): State<Arc<router::State>>,
+    jar: CookieJar,
+    Json(recipe): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?query, ?recipe);
+
+    let payload = serde_json::to_vec(&recipe).context("Failed to serialize recipe")?;
+    let value = query.seal.apply(&state.cookie_keys, &payload)?;
+
+    let cookie = Cookie::build((RECIPE_COOKIE, value))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict);
+
+    Ok((StatusCode::CREATED, jar.add(cookie)))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Unit {
+    Mg,
+    G,
+    Kg,
+    Oz,
+    Lb,
+    Ml,
+    L,
+    Tsp,
+    Tbsp,
+    Cups,
+    Pieces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Mass,
+    Volume,
+    Count,
+}
+
+impl Unit {
+    /// Dimension and exact factor to its base unit of grams, millilitres or pieces
+    fn base(&self) -> (Dimension, Decimal) {
+        use Dimension::*;
+
+        match self {
+            Unit::Mg => (Mass, Decimal::new(1, 3)),
+            Unit::G => (Mass, Decimal::ONE),
+            Unit::Kg => (Mass, Decimal::ONE_THOUSAND),
+            Unit::Oz => (Mass, Decimal::new(28_349_523_125, 9)),
+            Unit::Lb => (Mass, Decimal::new(45_359_237, 5)),
+            Unit::Ml => (Volume, Decimal::ONE),
+            Unit::L => (Volume, Decimal::ONE_THOUSAND),
+            Unit::Tsp => (Volume, Decimal::new(492_892_159_375, 11)),
+            Unit::Tbsp => (Volume, Decimal::new(1_478_676_478_125, 11)),
+            Unit::Cups => (Volume, Decimal::new(2_365_882_365, 7)),
+            Unit::Pieces => (Count, Decimal::ONE),
+        }
+    }
+
+    fn of(dimension: Dimension) -> Unit {
+        match dimension {
+            Dimension::Mass => Unit::G,
+            Dimension::Volume => Unit::Ml,
+            Dimension::Count => Unit::Pieces,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct Quantity {
+    amount: Amount,
+    unit: Unit,
+}
+
+impl Quantity {
+    /// `None` when the amount overflows in base units
+    fn base(&self) -> (Dimension, Option<Decimal>) {
+        let (dimension, factor) = self.unit.base();
+        (dimension, self.amount.0.checked_mul(factor))
+    }
+}
+
+fn default_weight() -> Amount {
+    Amount(Decimal::ONE)
+}
+
+#[derive(Debug, Deserialize)]
+struct PlannedRecipe {
+    ingredients: BTreeMap<String, Quantity>,
+    #[serde(default = "default_weight")]
+    weight: Amount,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlanInput {
+    recipes: BTreeMap<String, PlannedRecipe>,
+    pantry: BTreeMap<String, Quantity>,
+    #[serde(default)]
+    target: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct PlanOutput {
+    bakes: BTreeMap<String, u64>,
+    score: Amount,
+    pantry: BTreeMap<String, Quantity>,
+    shopping_list: BTreeMap<String, Quantity>,
+}
+
+// Search nodes explored before settling for the best plan found so far
+const PLAN_BUDGET: usize = 1_000_000;
+
+// Scores only rank plans, so a saturated one is still the best of them
+fn gain(weight: Decimal, count: u64) -> Decimal {
+    weight.saturating_mul(Decimal::from(count))
+}
+
+struct Planner {
+    weights: Vec<Decimal>,
+    /// Per recipe, amount of each ingredient in base units
+    needs: Vec<Vec<Decimal>>,
+    best: (Decimal, Vec<u64>),
+    budget: usize,
+}
+
+impl Planner {
+    fn max_alone(&self, recipe: usize, pantry: &[Decimal]) -> u64 {
+        self.needs[recipe]
+            .iter()
+            .zip(pantry)
+            .filter(|(need, _)| !need.is_zero())
+            .map(|(need, have)| {
+                have.checked_div(*need)
+                    .map_or(u64::MAX, |batches| batches.floor().to_u64().unwrap_or(0))
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn take(&self, recipe: usize, pantry: &mut [Decimal], count: u64) {
+        for (have, need) in pantry.iter_mut().zip(&self.needs[recipe]) {
+            *have -= need * Decimal::from(count);
+        }
+    }
+
+    fn give_back(&self, recipe: usize, pantry: &mut [Decimal], count: u64) {
+        for (have, need) in pantry.iter_mut().zip(&self.needs[recipe]) {
+            *have += need * Decimal::from(count);
+        }
+    }
+
+    fn search(
+        &mut self,
+        recipe: usize,
+        pantry: &mut [Decimal],
+        counts: &mut [u64],
+        score: Decimal,
+    ) {
+        if score > self.best.0 {
+            self.best = (score, counts.to_vec());
+        }
+        if recipe == self.weights.len() || self.budget == 0 {
+            return;
+        }
+        self.budget -= 1;
+
+        // Optimistic bound, every remaining recipe gets the whole pantry to itself
+        let bound = (recipe..self.weights.len())
+            .map(|rest| gain(self.weights[rest], self.max_alone(rest, pantry)))
+            .fold(Decimal::ZERO, Decimal::saturating_add);
+        if score.saturating_add(bound) <= self.best.0 {
+            return;
+        }
+
+        // Nothing is left to share the pantry with, so the last recipe takes all it can
+        if recipe + 1 == self.weights.len() {
+            let count = if self.weights[recipe].is_zero() {
+                0
+            } else {
+                self.max_alone(recipe, pantry)
+            };
+            let score = score.saturating_add(gain(self.weights[recipe], count));
+            if score > self.best.0 {
+                counts[recipe] = count;
+                self.best = (score, counts.to_vec());
+                counts[recipe] = 0;
+            }
+            return;
+        }
+
+        for count in (0..=self.max_alone(recipe, pantry)).rev() {
+            if self.budget == 0 {
+                break;
+            }
+            self.take(recipe, pantry, count);
+            counts[recipe] = count;
+
+            let score = score.saturating_add(gain(self.weights[recipe], count));
+            self.search(recipe + 1, pantry, counts, score);
+
+            self.give_back(recipe, pantry, count);
+            counts[recipe] = 0;
+        }
+    }
+}
+
+fn unprocessable(message: String) -> HttpError {
+    HttpError::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+}
+
+pub async fn plan(Json(input): Json<PlanInput>) -> Result<impl IntoResponse, Error> {
+    info!(?input);
+
+    let is_negative = |amount: &Amount| amount.0.is_sign_negative() && !amount.0.is_zero();
+    let amounts = input.pantry.iter().chain(
+        input
+            .recipes
+            .values()
+            .flat_map(|recipe| &recipe.ingredients),
+    );
+    for (name, quantity) in amounts {
+        if is_negative(&quantity.amount) {
+            return Err(unprocessable(format!("{} must not be negative", name)).into());
+        }
+    }
+    if let Some((name, _)) = input
+        .recipes
+        .iter()
+        .find(|(_, recipe)| is_negative(&recipe.weight))
+    {
+        return Err(unprocessable(format!("{} must not have a negative weight", name)).into());
+    }
+
+    // Every ingredient is tracked in the base unit of the first dimension seen for it
+    let mut dimensions = BTreeMap::<String, Dimension>::new();
+    let quantities = input.pantry.iter().chain(
+        input
+            .recipes
+            .values()
+            .flat_map(|recipe| &recipe.ingredients),
+    );
+    for (name, quantity) in quantities {
+        let (dimension, amount) = quantity.base();
+        if amount.is_none() {
+            return Err(overflow(name).into());
+        }
+        if *dimensions.entry(name.clone()).or_insert(dimension) != dimension {
+            return Err(
+                unprocessable(format!("{} is measured in incompatible units", name)).into(),
+            );
+        }
+    }
+
+    let ingredients = dimensions.keys().cloned().collect::<Vec<_>>();
+    let base = |quantities: &BTreeMap<String, Quantity>| {
+        ingredients
+            .iter()
+            .map(|name| {
+                quantities
+                    .get(name)
+                    .and_then(|quantity| quantity.base().1)
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let names = input.recipes.keys().cloned().collect::<Vec<_>>();
+    let mut planner = Planner {
+        weights: names
+            .iter()
+            .map(|name| input.recipes[name].weight.0)
+            .collect(),
+        needs: names
+            .iter()
+            .map(|name| base(&input.recipes[name].ingredients))
+            .collect(),
+        best: (Decimal::ZERO, vec![0; names.len()]),
+        budget: PLAN_BUDGET,
+    };
+    if let Some(name) = names
+        .iter()
+        .zip(&planner.needs)
+        .find(|(_, needs)| needs.iter().all(|need| need.is_zero()))
+        .map(|(name, _)| name)
+    {
+        return Err(unprocessable(format!("{} needs no ingredients", name)).into());
+    }
+
+    let mut pantry = base(&input.pantry);
+    planner.search(0, &mut pantry, &mut vec![0; names.len()], Decimal::ZERO);
+    let (score, counts) = planner.best.clone();
+
+    let mut remaining = pantry.clone();
+    for (recipe, count) in counts.iter().enumerate() {
+        planner.take(recipe, &mut remaining, *count);
+    }
+
+    let mut shopping_list = BTreeMap::new();
+    for (index, name) in ingredients.iter().enumerate() {
+        let needed = input
+            .target
+            .iter()
+            .map(|(recipe, count)| {
+                names
+                    .binary_search(recipe)
+                    .map(|recipe| gain(planner.needs[recipe][index], *count))
+                    .map_err(|_| unprocessable(format!("Unknown target recipe {}", recipe)))
+            })
+            .try_fold(Decimal::ZERO, |total, needed| {
+                needed.map(|needed| total.saturating_add(needed))
+            })?;
+        if needed > pantry[index] {
+            shopping_list.insert(
+                name.clone(),
+                Quantity {
+                    amount: Amount(needed - pantry[index]),
+                    unit: Unit::of(dimensions[name]),
+                },
+            );
+        }
+    }
+
+    let output = PlanOutput {
+        bakes: names.into_iter().zip(counts).collect(),
+        score: Amount(score),
+        pantry: ingredients
+            .into_iter()
+            .zip(remaining)
+            .map(|(name, amount)| {
+                let unit = Unit::of(dimensions[&name]);
+                (
+                    name,
+                    Quantity {
+                        amount: Amount(amount),
+                        unit,
+                    },
+                )
+            })
+            .collect(),
+        shopping_list,
+    };
+    info!(?output);
+
+    Ok(Json(output))
+}
+
+async fn load_pantry(pool: &PgPool, user: &str) -> Result<Pantry, Error> {
+    let pantry = sqlx::query_scalar!(
+        r#"SELECT pantry AS "pantry: types::Json<Pantry>" FROM pantries WHERE user_name = $1"#,
+        user
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to select pantry")?
+    .map(|pantry| pantry.0)
+    .unwrap_or_default();
+
+    Ok(pantry)
+}
+
+pub async fn pantry_get(
+    Path(user): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    Ok(Json(load_pantry(&state.pool, &user).await?))
+}
+
+pub async fn pantry_restock(
+    Path(user): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Json(restock): Json<Pantry>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?user, ?restock);
+
+    let mut transaction = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to init transaction")?;
+
+    // A first restock has no row to lock yet, so an empty one is created first and
+    // concurrent first restocks queue on the same row lock
+    create_pantry(&mut transaction, &user).await?;
+    let mut pantry = lock_pantry(&mut transaction, &user)
+        .await?
+        .unwrap_or_default();
+
+    for (ingredient, amount) in restock {
+        if amount.0.is_sign_negative() && !amount.0.is_zero() {
+            return Err(unprocessable(format!("{} must not be negative", ingredient)).into());
+        }
+        let stock = pantry.entry(ingredient.clone()).or_default();
+        stock.0 = stock
+            .0
+            .checked_add(amount.0)
+            .ok_or_else(|| overflow(&ingredient))?;
+    }
+
+    save_pantry(&mut transaction, &user, &pantry).await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit restock")?;
+
+    Ok(Json(pantry))
+}
+
+async fn create_pantry(
+    transaction: &mut Transaction<'_, Postgres>,
+    user: &str,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO pantries (
+            user_name,
+            pantry
+        ) VALUES (
+            $1,
+            '{}'
+        )
+        ON CONFLICT (user_name) DO NOTHING
+        "#,
+        user
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to create pantry")?;
+
+    Ok(())
+}
+
+// Row lock keeps concurrent bakes and restocks from racing each other
+async fn lock_pantry(
+    transaction: &mut Transaction<'_, Postgres>,
+    user: &str,
+) -> Result<Option<Pantry>, Error> {
+    let pantry = sqlx::query_scalar!(
+        r#"SELECT pantry AS "pantry: types::Json<Pantry>" FROM pantries WHERE user_name = $1 FOR UPDATE"#,
+        user
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to select pantry")?
+    .map(|pantry| pantry.0);
+
+    Ok(pantry)
+}
+
+async fn save_pantry(
+    transaction: &mut Transaction<'_, Postgres>,
+    user: &str,
+    pantry: &Pantry,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO pantries (
+            user_name,
+            pantry
+        ) VALUES (
+            $1,
+            $2
+        )
+        ON CONFLICT (user_name) DO UPDATE SET pantry = EXCLUDED.pantry
+        "#,
+        user,
+        serde_json::to_value(pantry).context("Failed to serialize pantry")?
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to upsert pantry")?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BakeRequest {
+    recipe: Pantry,
+    /// Bake exactly this many or fail, otherwise bake as many as possible
+    cookies: Option<u64>,
+    /// Report what would be baked without touching the pantry
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct BakeRecord {
+    id: i32,
+    baked_at: DateTime<Utc>,
+    recipe: types::Json<Pantry>,
+    cookies: i64,
+    pantry_before: types::Json<Pantry>,
+    pantry_after: types::Json<Pantry>,
+}
+
+pub async fn pantry_bake(
+    Path(user): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Json(request): Json<BakeRequest>,
+) -> Result<impl IntoResponse, Error> {
+    info!(?user, ?request);
+
+    let mut transaction = state
+        .pool
+        .begin()
+        .await
+        .context("Failed to init transaction")?;
+
+    let before = lock_pantry(&mut transaction, &user)
+        .await?
+        .ok_or_else(|| HttpError::not_found(format!("{} has no pantry", user)))?;
+
+    let output = bake(&request.recipe, before.clone(), request.cookies)?;
+    if let Some(cookies) = request.cookies.filter(|cookies| *cookies > output.cookies) {
+        return Err(HttpError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Pantry only has enough for {} of {} cookies",
+                output.cookies, cookies
+            ),
+        )
+        .into());
+    }
+
+    if request.dry_run {
+        info!(?output, "dry run");
+        return Ok(Json(output).into_response());
+    }
+
+    save_pantry(&mut transaction, &user, &output.pantry).await?;
+
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO bakes (
+            user_name,
+            recipe,
+            cookies,
+            pantry_before,
+            pantry_after
+        ) VALUES (
+            $1,
+            $2,
+            $3,
+            $4,
+            $5
+        )
+        RETURNING id, baked_at
+        "#,
+        user,
+        serde_json::to_value(&request.recipe).context("Failed to serialize recipe")?,
+        output.cookies as i64,
+        serde_json::to_value(&before).context("Failed to serialize pantry")?,
+        serde_json::to_value(&output.pantry).context("Failed to serialize pantry")?
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to insert bake")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit bake")?;
+
+    let record = BakeRecord {
+        id: record.id,
+        baked_at: record.baked_at,
+        recipe: types::Json(request.recipe),
+        cookies: output.cookies as i64,
+        pantry_before: types::Json(before),
+        pantry_after: types::Json(output.pantry),
+    };
+    info!(?record);
+
+    Ok((StatusCode::CREATED, Json(record)).into_response())
+}
+
+pub async fn pantry_history(
+    Path(user): Path<String>,
+    pagination: Pagination,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let total = sqlx::query_scalar!("SELECT COUNT(*) FROM bakes WHERE user_name = $1", user)
+        .fetch_one(&state.pool)
+        .await
+        .context("Failed to count bakes")?
+        .unwrap_or(0);
+
+    let bakes = sqlx::query_as!(
+        BakeRecord,
+        r#"
+        SELECT
+            id,
+            baked_at,
+            recipe AS "recipe: types::Json<Pantry>",
+            cookies,
+            pantry_before AS "pantry_before: types::Json<Pantry>",
+            pantry_after AS "pantry_after: types::Json<Pantry>"
+        FROM bakes
+        WHERE user_name = $1
+        ORDER BY baked_at
+        LIMIT $2
+        OFFSET $3
+        "#,
+        user,
+        pagination.limit().map(|limit| limit as i64),
+        pagination.offset() as i64
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to select bakes")?;
+
+    Ok(pagination.page(bakes, total as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue_then_bake(keys: &CookieKeys, seal: Seal) -> Result<BakeOutput, Error> {
+        let recipe = serde_json::json!({
+            "recipe": { "flour": 100, "sugar": 20 },
+            "pantry": { "flour": 250, "sugar": 100 },
+        });
+        let payload = serde_json::to_vec(&recipe).unwrap();
+        let value = seal.apply(keys, &payload)?;
+
+        let jar = CookieJar::new().add(Cookie::new(RECIPE_COOKIE, value));
+        bake_cookie(&jar, keys)
+    }
+
+    #[test]
+    fn sealed_cookies_bake_with_and_without_legacy() {
+        for legacy in [false, true] {
+            let keys = CookieKeys::generate().with_legacy(legacy);
+            for seal in [Seal::Signed, Seal::Encrypted] {
+                let output = issue_then_bake(&keys, seal).unwrap();
+                assert_eq!(output.cookies, 2, "{:?} with legacy {}", seal, legacy);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_cookies_are_only_issued_when_bake_accepts_them() {
+        let keys = CookieKeys::generate().with_legacy(true);
+        assert_eq!(issue_then_bake(&keys, Seal::Plain).unwrap().cookies, 2);
+
+        let keys = CookieKeys::generate();
+        assert!(Seal::Plain.apply(&keys, b"{}").is_err());
+        let jar = CookieJar::new().add(Cookie::new(RECIPE_COOKIE, rbase64::encode(b"{}")));
+        assert!(bake_cookie(&jar, &keys).is_err());
+    }
 }
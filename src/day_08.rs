@@ -1,33 +1,46 @@
 use std::sync::Arc;
 
-use anyhow::Context;
 use axum::{
     extract::{Path, State},
     response::IntoResponse,
+    Json,
 };
+use axum_extra::extract::Query;
+use futures::{stream, StreamExt, TryStreamExt};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use tracing::{debug, info};
 
-use crate::router::{self, Error};
+use crate::{
+    router::{self, Error},
+    validation::{invalid, FieldError, ValidationFailed},
+};
+
+const MAX_COMPARED: usize = 10;
+// Lookups in flight per request, the upstream and its circuit breaker are shared
+const COMPARE_CONCURRENCY: usize = 4;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Pokemon {
+    #[serde(default)]
+    id: i32,
+    #[serde(default)]
+    name: String,
     weight: i32,
 }
 
+impl Pokemon {
+    // PokeAPI reports weight in hectograms
+    fn mass(&self) -> f64 {
+        self.weight as f64 / 10f64
+    }
+}
+
 pub async fn task_01(
     Path(number): Path<i32>,
     State(state): State<Arc<router::State>>,
 ) -> Result<impl IntoResponse, Error> {
-    let poke: Pokemon = state
-        .client
-        .get(format!("https://pokeapi.co/api/v2/pokemon/{}", number))
-        .send()
-        .await
-        .context("Failed to send pokeapi request")?
-        .json()
-        .await
-        .context("Failed to parse response json")?;
+    let poke: Pokemon = state.pokeapi.pokemon(&number.to_string()).await?;
 
     debug!(?poke);
     Ok((poke.weight as f32 / 10f32).to_string())
@@ -39,15 +52,214 @@ pub async fn task_02(
     Path(number): Path<i32>,
     State(state): State<Arc<router::State>>,
 ) -> Result<impl IntoResponse, Error> {
-    let poke: Pokemon = state
-        .client
-        .get(format!("https://pokeapi.co/api/v2/pokemon/{}", number))
-        .send()
-        .await
-        .context("Failed to send pokeapi request")?
-        .json()
-        .await
-        .context("Failed to parse response json")?;
+    let poke: Pokemon = state.pokeapi.pokemon(&number.to_string()).await?;
 
     Ok((GRAV.sqrt() * (poke.weight as f32 / 10f32)).to_string())
 }
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Planet {
+    Mercury,
+    Venus,
+    Earth,
+    Moon,
+    Mars,
+    Jupiter,
+    Saturn,
+    Uranus,
+    Neptune,
+}
+
+impl Planet {
+    fn gravity(self) -> f64 {
+        match self {
+            Planet::Mercury => 3.7,
+            Planet::Venus => 8.87,
+            Planet::Earth => 9.825,
+            Planet::Moon => 1.62,
+            Planet::Mars => 3.721,
+            Planet::Jupiter => 24.79,
+            Planet::Saturn => 10.44,
+            Planet::Uranus => 8.69,
+            Planet::Neptune => 11.15,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Fall {
+    #[serde(default)]
+    pokemon: Vec<String>,
+    height: Option<f64>,
+    gravity: Option<f64>,
+    planet: Option<Planet>,
+    /// Quadratic drag coefficient in kg/m, zero falls in a vacuum
+    drag: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Conditions {
+    height: f64,
+    gravity: f64,
+    drag: f64,
+}
+
+impl TryFrom<&Fall> for Conditions {
+    type Error = ValidationFailed;
+
+    fn try_from(fall: &Fall) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        if fall.gravity.is_some() && fall.planet.is_some() {
+            errors.push(FieldError::new(
+                0,
+                "gravity",
+                "cannot be combined with planet",
+            ));
+        }
+        let mut check = |field: &str, value: f64, valid: bool, message: &str| {
+            if !valid || !value.is_finite() {
+                errors.push(FieldError::new(0, field, message));
+            }
+            value
+        };
+
+        let height = fall.height.unwrap_or(10f64);
+        let gravity = fall
+            .gravity
+            .or(fall.planet.map(Planet::gravity))
+            .unwrap_or(Planet::Earth.gravity());
+        let drag = fall.drag.unwrap_or_default();
+        let conditions = Conditions {
+            height: check("height", height, height > 0f64, "must be greater than zero"),
+            gravity: check(
+                "gravity",
+                gravity,
+                gravity > 0f64,
+                "must be greater than zero",
+            ),
+            drag: check("drag", drag, drag >= 0f64, "must not be negative"),
+        };
+
+        if !errors.is_empty() {
+            return Err(ValidationFailed { errors });
+        }
+        Ok(conditions)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Impact {
+    id: i32,
+    name: String,
+    mass: f64,
+    velocity: f64,
+    time: f64,
+    energy: f64,
+    momentum: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terminal_velocity: Option<f64>,
+}
+
+// acosh(e^x) without overflowing e^x for long drops
+fn acosh_exp(x: f64) -> f64 {
+    x + (1f64 + (1f64 - (-2f64 * x).exp()).sqrt()).ln()
+}
+
+impl Conditions {
+    fn impact(&self, poke: Pokemon) -> Impact {
+        let Conditions {
+            height,
+            gravity,
+            drag,
+        } = *self;
+        let mass = poke.mass();
+
+        // Massless Pokémon can't be slowed by drag in any meaningful way, fall as in a vacuum
+        let terminal_velocity =
+            (drag > 0f64 && mass > 0f64).then(|| (mass * gravity / drag).sqrt());
+        let (velocity, time) = match terminal_velocity {
+            Some(vt) => (
+                vt * (1f64 - (-2f64 * gravity * height / vt.powi(2)).exp()).sqrt(),
+                vt / gravity * acosh_exp(gravity * height / vt.powi(2)),
+            ),
+            None => (
+                (2f64 * gravity * height).sqrt(),
+                (2f64 * height / gravity).sqrt(),
+            ),
+        };
+
+        Impact {
+            id: poke.id,
+            name: poke.name,
+            mass,
+            velocity,
+            time,
+            energy: mass * velocity.powi(2) / 2f64,
+            momentum: mass * velocity,
+            terminal_velocity,
+        }
+    }
+}
+
+pub async fn physics(
+    Path(pokemon): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Query(fall): Query<Fall>,
+) -> Result<impl IntoResponse, Error> {
+    let conditions = Conditions::try_from(&fall)?;
+    let poke: Pokemon = state.pokeapi.pokemon(&pokemon).await?;
+
+    let impact = conditions.impact(poke);
+    info!(?impact);
+    Ok(Json(impact))
+}
+
+#[derive(Debug, Serialize)]
+struct Comparison {
+    impacts: Vec<Impact>,
+    hardest: Option<String>,
+}
+
+pub async fn compare(
+    State(state): State<Arc<router::State>>,
+    Query(fall): Query<Fall>,
+) -> Result<impl IntoResponse, Error> {
+    let conditions = Conditions::try_from(&fall)?;
+    let names = fall
+        .pokemon
+        .iter()
+        .map(|name| name.to_ascii_lowercase())
+        .unique()
+        .collect::<Vec<_>>();
+    if names.is_empty() || names.len() > MAX_COMPARED {
+        return Err(invalid(
+            0,
+            "pokemon",
+            format!("between 1 and {} Pokémon are required", MAX_COMPARED),
+        )
+        .into());
+    }
+
+    let pokeapi = &state.pokeapi;
+    let mut pokemon: Vec<(usize, Pokemon)> = stream::iter(names.iter().enumerate())
+        .map(|(index, name)| async move {
+            let poke: Pokemon = pokeapi.pokemon(name).await?;
+            Ok::<_, Error>((index, poke))
+        })
+        .buffer_unordered(COMPARE_CONCURRENCY)
+        .try_collect()
+        .await?;
+    pokemon.sort_by_key(|(index, _)| *index);
+    let impacts = pokemon
+        .into_iter()
+        .map(|(_, poke)| conditions.impact(poke))
+        .collect::<Vec<_>>();
+    let hardest = impacts
+        .iter()
+        .max_by(|a, b| a.energy.total_cmp(&b.energy))
+        .map(|impact| impact.name.clone());
+
+    info!(?hardest);
+    Ok(Json(Comparison { impacts, hardest }))
+}
@@ -1,39 +1,913 @@
-use std::{io::Cursor, num::Saturating};
+use std::{collections::BTreeMap, io::Cursor, num::Saturating, str::FromStr, sync::Arc};
 
 use anyhow::Context;
-use axum::{extract::Multipart, response::IntoResponse};
-use image::io::Reader as ImageReader;
+use axum::{
+    extract::{multipart::Field, Multipart, State},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::Query;
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader as ImageReader},
+    DynamicImage, ImageError, ImageFormat, ImageOutputFormat,
+};
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use shuttle_runtime::SecretStore;
 use tracing::info;
 
-use crate::router::Error;
+use crate::{
+    pixel_expr::{self, Channel, PixelExpr},
+    router::{self, Error, HttpError},
+    validation::{invalid, FieldError, ValidationFailed},
+};
 
-pub async fn task_02(mut multipart: Multipart) -> Result<impl IntoResponse, Error> {
-    let mut count = 0;
-    while let Some(field) = multipart
+/// Upload limits, checked while streaming a field and against the header
+/// dimensions before any pixels are decoded
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    pub max_pixels: u64,
+    pub max_field_bytes: usize,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        ImageLimits {
+            max_pixels: 40_000_000,
+            max_field_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+impl ImageLimits {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let default = ImageLimits::default();
+        ImageLimits {
+            max_pixels: router::secret(secrets, "IMAGE_MAX_PIXELS").unwrap_or(default.max_pixels),
+            max_field_bytes: router::secret(secrets, "IMAGE_MAX_FIELD_BYTES")
+                .unwrap_or(default.max_field_bytes),
+        }
+    }
+}
+
+async fn read_field(field: &mut Field<'_>, limits: &ImageLimits) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await.context("Failed to get bytes")? {
+        if data.len() + chunk.len() > limits.max_field_bytes {
+            return Err(HttpError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Field `{}` exceeds {} bytes",
+                    field.name().unwrap_or("unknown"),
+                    limits.max_field_bytes
+                ),
+            )
+            .into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+fn format_name(format: ImageFormat) -> String {
+    format!("{:?}", format).to_lowercase()
+}
+
+fn red_pixels(img: DynamicImage) -> usize {
+    img.into_rgb16()
+        .as_raw()
+        .par_chunks_exact(3)
+        .filter(|p| (Saturating(p[0]) - Saturating(p[1])) > Saturating(p[2]))
+        .count()
+}
+
+#[derive(Debug, Serialize)]
+struct FieldReport {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    red_pixels: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct RedPixelsReport {
+    total: usize,
+    fields: Vec<FieldReport>,
+}
+
+pub async fn task_02(
+    State(state): State<Arc<router::State>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Error> {
+    let mut fields = Vec::new();
+    while let Some(mut field) = multipart
         .next_field()
         .await
         .context("Failed to get multipart field")?
     {
         let name = field.name().unwrap_or("unknown").to_string();
-        let data = field.bytes().await.context("Failed to get bytes")?;
+        let filename = field.file_name().map(str::to_string);
+        let data = read_field(&mut field, &state.image_limits).await?;
 
         info!("Length of `{}` is {} bytes", name, data.len());
 
+        let mut report = FieldReport {
+            name,
+            filename,
+            size: data.len(),
+            format: None,
+            width: None,
+            height: None,
+            red_pixels: None,
+        };
         // I felt like specifically checking for image
-        if name == "image" {
-            let img = ImageReader::new(Cursor::new(data))
-                .with_guessed_format()
-                .context("Failed to guess format")?
-                .decode()
-                .context("Failed to decode format")?
-                .into_rgb16();
-            // I also wanted to parse instances where multiple images are sent
-            count += img
-                .pixels()
-                .filter(|p| (Saturating(p.0[0]) - Saturating(p.0[1])) > Saturating(p.0[2]))
-                .count();
-        }
-    }
-
-    Ok(count.to_string())
+        if report.name == "image" {
+            let limits = state.image_limits;
+            let (format, width, height, red) = state
+                .image_pool
+                .run(move || {
+                    let (format, img) = decode_limited(&data, &limits)?;
+                    Ok((format, img.width(), img.height(), red_pixels(img)))
+                })
+                .await?;
+            report.format = format.map(format_name);
+            report.width = Some(width);
+            report.height = Some(height);
+            report.red_pixels = Some(red);
+        }
+        fields.push(report);
+    }
+
+    // I also wanted to parse instances where multiple images are sent
+    let total = fields.iter().filter_map(|field| field.red_pixels).sum();
+    Ok(Json(RedPixelsReport { total, fields }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyzeOptions {
+    #[serde(default)]
+    hsv: Vec<String>,
+    #[serde(default)]
+    expr: Vec<String>,
+    k: Option<usize>,
+    bins: Option<usize>,
+}
+
+const DEFAULT_CLUSTERS: usize = 5;
+const MAX_CLUSTERS: usize = 16;
+const KMEANS_SAMPLES: usize = 4096;
+const KMEANS_ITERATIONS: usize = 20;
+
+/// Inclusive HSV bounds written as `h0..h1,s0..s1,v0..v1`, hue wraps when
+/// `h0 > h1` so reds can be matched with `330..30`
+#[derive(Debug)]
+struct HsvRange([(f64, f64); 3]);
+
+impl FromStr for HsvRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|range| {
+                let (min, max) = range
+                    .split_once("..")
+                    .ok_or_else(|| format!("`{}` is not a `min..max` range", range))?;
+                let bound = |bound: &str| {
+                    bound
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| format!("`{}` is not a number", bound))
+                };
+                Ok((bound(min)?, bound(max)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let ranges: [(f64, f64); 3] = ranges
+            .try_into()
+            .map_err(|_| "expected hue, saturation and value ranges".to_string())?;
+        Ok(HsvRange(ranges))
+    }
+}
+
+impl HsvRange {
+    fn matches(&self, channels: &[f64; Channel::COUNT]) -> bool {
+        let [(h0, h1), (s0, s1), (v0, v1)] = self.0;
+        let hue = channels[Channel::H as usize];
+        let hue_matches = if h0 <= h1 {
+            (h0..=h1).contains(&hue)
+        } else {
+            hue >= h0 || hue <= h1
+        };
+
+        hue_matches
+            && (s0..=s1).contains(&channels[Channel::S as usize])
+            && (v0..=v1).contains(&channels[Channel::V as usize])
+    }
+}
+
+enum Predicate {
+    Hsv(HsvRange),
+    Expr(PixelExpr),
+}
+
+impl Predicate {
+    fn matches(&self, channels: &[f64; Channel::COUNT]) -> bool {
+        match self {
+            Predicate::Hsv(range) => range.matches(channels),
+            Predicate::Expr(expr) => expr.matches(channels),
+        }
+    }
+}
+
+struct Analysis {
+    clusters: usize,
+    bins: usize,
+    predicates: Vec<(String, Predicate)>,
+}
+
+impl TryFrom<AnalyzeOptions> for Analysis {
+    type Error = ValidationFailed;
+
+    fn try_from(options: AnalyzeOptions) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        let mut invalid = |index: usize, field: &str, message: String| {
+            errors.push(FieldError::new(index, field, message))
+        };
+
+        let clusters = options.k.unwrap_or(DEFAULT_CLUSTERS);
+        if !(1..=MAX_CLUSTERS).contains(&clusters) {
+            invalid(0, "k", format!("must be between 1 and {}", MAX_CLUSTERS));
+        }
+        let bins = options.bins.unwrap_or(256);
+        if !(1..=256).contains(&bins) {
+            invalid(0, "bins", "must be between 1 and 256".to_string());
+        }
+
+        let hsv = options.hsv.into_iter().enumerate().map(|(index, raw)| {
+            let parsed = raw.parse().map(Predicate::Hsv);
+            (index, "hsv", raw, parsed)
+        });
+        let expr = options.expr.into_iter().enumerate().map(|(index, raw)| {
+            let parsed = PixelExpr::parse(&raw).map(Predicate::Expr);
+            (index, "expr", raw, parsed)
+        });
+        let predicates = hsv
+            .chain(expr)
+            .filter_map(|(index, field, raw, parsed)| {
+                parsed
+                    .map_err(|message| invalid(index, field, message))
+                    .ok()
+                    .map(|predicate| (format!("{}:{}", field, raw), predicate))
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(ValidationFailed { errors });
+        }
+        Ok(Analysis {
+            clusters,
+            bins,
+            predicates,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Histograms {
+    red: Vec<u64>,
+    green: Vec<u64>,
+    blue: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alpha: Option<Vec<u64>>,
+}
+
+#[derive(Debug, Serialize)]
+struct DominantColor {
+    hex: String,
+    rgb: [u8; 3],
+    share: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct PredicateCount {
+    predicate: String,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageReport {
+    width: u32,
+    height: u32,
+    format: Option<String>,
+    color_type: String,
+    exif: BTreeMap<String, String>,
+    brightness: f64,
+    histograms: Histograms,
+    dominant_colors: Vec<DominantColor>,
+    counts: Vec<PredicateCount>,
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+fn nearest(centroids: &[[f64; 3]], point: &[f64; 3]) -> usize {
+    centroids
+        .iter()
+        .map(|centroid| distance(centroid, point))
+        .position_min_by(|a, b| a.total_cmp(b))
+        .unwrap_or_default()
+}
+
+// Deterministic seeding, each new centroid is the sample furthest from the existing ones
+fn kmeans(samples: &[[f64; 3]], k: usize) -> Vec<DominantColor> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+
+    let mut centroids = vec![samples[0]];
+    while centroids.len() < k.min(samples.len()) {
+        let furthest = samples
+            .iter()
+            .max_by(|a, b| {
+                let a = distance(&centroids[nearest(&centroids, a)], a);
+                let b = distance(&centroids[nearest(&centroids, b)], b);
+                a.total_cmp(&b)
+            })
+            .copied()
+            .unwrap_or(samples[0]);
+        centroids.push(furthest);
+    }
+
+    let mut assignments = vec![usize::MAX; samples.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (assignment, sample) in assignments.iter_mut().zip(samples) {
+            let cluster = nearest(&centroids, sample);
+            changed |= *assignment != cluster;
+            *assignment = cluster;
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![([0f64; 3], 0usize); centroids.len()];
+        for (&cluster, sample) in assignments.iter().zip(samples) {
+            let (sum, count) = &mut sums[cluster];
+            sum.iter_mut()
+                .zip(sample)
+                .for_each(|(sum, value)| *sum += value);
+            *count += 1;
+        }
+        for (centroid, (sum, count)) in centroids.iter_mut().zip(sums) {
+            if count > 0 {
+                *centroid = sum.map(|sum| sum / count as f64);
+            }
+        }
+    }
+
+    let counts = assignments.iter().counts();
+    centroids
+        .into_iter()
+        .enumerate()
+        .filter_map(|(cluster, centroid)| {
+            let count = *counts.get(&cluster)?;
+            let rgb = centroid.map(|channel| channel.round() as u8);
+            Some(DominantColor {
+                hex: format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]),
+                rgb,
+                share: count as f64 / samples.len() as f64,
+            })
+        })
+        .sorted_by(|a, b| b.share.total_cmp(&a.share))
+        .collect()
+}
+
+fn read_exif(data: &[u8]) -> BTreeMap<String, String> {
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) else {
+        return BTreeMap::new();
+    };
+
+    exif.fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY)
+        .map(|field| {
+            (
+                field.tag.to_string(),
+                field.display_value().with_unit(&exif).to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Per thread accumulator for the pixel scan, merged once every chunk is done
+struct Scan {
+    histograms: [Vec<u64>; 4],
+    counts: Vec<usize>,
+    luma: f64,
+}
+
+impl Scan {
+    fn new(analysis: &Analysis) -> Self {
+        Scan {
+            histograms: [(); 4].map(|_| vec![0u64; analysis.bins]),
+            counts: vec![0usize; analysis.predicates.len()],
+            luma: 0f64,
+        }
+    }
+
+    fn add(&mut self, pixel: [u8; 4], analysis: &Analysis) {
+        for (histogram, value) in self.histograms.iter_mut().zip(pixel) {
+            histogram[value as usize * analysis.bins / 256] += 1;
+        }
+
+        let channels = pixel_expr::channels(pixel);
+        self.luma += channels[Channel::L as usize];
+        for (count, (_, predicate)) in self.counts.iter_mut().zip(&analysis.predicates) {
+            *count += predicate.matches(&channels) as usize;
+        }
+    }
+
+    fn merge(mut self, other: Scan) -> Scan {
+        for (histogram, other) in self.histograms.iter_mut().zip(other.histograms) {
+            histogram.iter_mut().zip(other).for_each(|(a, b)| *a += b);
+        }
+        self.counts
+            .iter_mut()
+            .zip(other.counts)
+            .for_each(|(a, b)| *a += b);
+        self.luma += other.luma;
+        self
+    }
+}
+
+fn analyze_image(
+    data: &[u8],
+    analysis: &Analysis,
+    limits: &ImageLimits,
+) -> Result<ImageReport, Error> {
+    let (format, img) = decode_limited(data, limits)?;
+    let color_type = img.color();
+    let rgba = img.into_rgba8();
+
+    let Scan {
+        histograms,
+        counts,
+        luma,
+    } = rgba
+        .as_raw()
+        .par_chunks_exact(4)
+        .fold(
+            || Scan::new(analysis),
+            |mut scan, pixel| {
+                scan.add([pixel[0], pixel[1], pixel[2], pixel[3]], analysis);
+                scan
+            },
+        )
+        .reduce(|| Scan::new(analysis), Scan::merge);
+
+    let pixels = rgba.pixels().len();
+    // Fully transparent pixels carry no colour worth reporting
+    let samples = rgba
+        .pixels()
+        .filter(|pixel| pixel.0[3] > 0)
+        .step_by((pixels / KMEANS_SAMPLES).max(1))
+        .map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2]].map(f64::from))
+        .collect::<Vec<_>>();
+    let [red, green, blue, alpha] = histograms;
+
+    Ok(ImageReport {
+        width: rgba.width(),
+        height: rgba.height(),
+        format: format.map(format_name),
+        color_type: format!("{:?}", color_type),
+        exif: read_exif(data),
+        brightness: if pixels == 0 {
+            0f64
+        } else {
+            luma / pixels as f64 / 255f64
+        },
+        histograms: Histograms {
+            red,
+            green,
+            blue,
+            alpha: color_type.has_alpha().then_some(alpha),
+        },
+        dominant_colors: kmeans(&samples, analysis.clusters),
+        counts: analysis
+            .predicates
+            .iter()
+            .zip(counts)
+            .map(|((predicate, _), count)| PredicateCount {
+                predicate: predicate.clone(),
+                count,
+            })
+            .collect(),
+    })
+}
+
+pub async fn analyze(
+    State(state): State<Arc<router::State>>,
+    Query(options): Query<AnalyzeOptions>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Error> {
+    let analysis = Analysis::try_from(options)?;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .context("Failed to get multipart field")?
+    {
+        if field.name() != Some("image") {
+            continue;
+        }
+
+        let data = read_field(&mut field, &state.image_limits).await?;
+        let limits = state.image_limits;
+        let report = state
+            .image_pool
+            .run(move || analyze_image(&data, &analysis, &limits))
+            .await?;
+        info!(
+            width = report.width,
+            height = report.height,
+            brightness = report.brightness
+        );
+        return Ok(Json(report));
+    }
+
+    Err(HttpError::new(StatusCode::BAD_REQUEST, "Missing `image` field").into())
+}
+
+const MAX_DIMENSION: u32 = 8192;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+const MAX_OPERATIONS: usize = 10;
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    /// Exact size, ignoring the aspect ratio unless `keep_aspect` is set
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        filter: ResizeFilter,
+        #[serde(default)]
+        keep_aspect: bool,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Rotate {
+        degrees: u32,
+    },
+    Grayscale,
+    Blur {
+        sigma: f32,
+    },
+    /// Fast downscale that keeps the aspect ratio and never enlarges
+    Thumbnail {
+        width: u32,
+        height: u32,
+    },
+    Convert {
+        format: OutputFormat,
+        quality: Option<u8>,
+    },
+}
+
+impl Operation {
+    fn validate(&self) -> Result<(), String> {
+        let dimensions = |width: u32, height: u32| {
+            if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+                return Err(format!(
+                    "dimensions must be between 1 and {}",
+                    MAX_DIMENSION
+                ));
+            }
+            Ok(())
+        };
+
+        match *self {
+            Operation::Resize { width, height, .. } | Operation::Thumbnail { width, height } => {
+                dimensions(width, height)
+            }
+            Operation::Crop { width, height, .. } => dimensions(width, height),
+            Operation::Rotate { degrees } if degrees % 90 != 0 => {
+                Err("rotation must be a multiple of 90 degrees".to_string())
+            }
+            Operation::Blur { sigma } if !(sigma > 0f32 && sigma <= 100f32) => {
+                Err("sigma must be between 0 and 100".to_string())
+            }
+            Operation::Convert {
+                quality: Some(quality),
+                ..
+            } if !(1..=100).contains(&quality) => {
+                Err("quality must be between 1 and 100".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Size of the image this operation would produce from a `width`x`height` one
+    fn output_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        // Same fit as `DynamicImage::resize`, up to rounding
+        let fit = |max_width: u32, max_height: u32| {
+            let ratio = f64::min(
+                max_width as f64 / width.max(1) as f64,
+                max_height as f64 / height.max(1) as f64,
+            );
+            (
+                (width as f64 * ratio).round().max(1f64) as u32,
+                (height as f64 * ratio).round().max(1f64) as u32,
+            )
+        };
+
+        match *self {
+            Operation::Resize {
+                width,
+                height,
+                keep_aspect: false,
+                ..
+            } => (width, height),
+            Operation::Resize {
+                width: max_width,
+                height: max_height,
+                keep_aspect: true,
+                ..
+            } => fit(max_width, max_height),
+            Operation::Crop { width, height, .. } => (width, height),
+            Operation::Rotate { degrees } if degrees % 180 == 90 => (height, width),
+            Operation::Thumbnail {
+                width: max_width,
+                height: max_height,
+            } if width > max_width || height > max_height => fit(max_width, max_height),
+            _ => (width, height),
+        }
+    }
+
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String> {
+        Ok(match *self {
+            Operation::Resize {
+                width,
+                height,
+                filter,
+                keep_aspect: false,
+            } => img.resize_exact(width, height, filter.into()),
+            Operation::Resize {
+                width,
+                height,
+                filter,
+                keep_aspect: true,
+            } => img.resize(width, height, filter.into()),
+            Operation::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                if x.saturating_add(width) > img.width() || y.saturating_add(height) > img.height()
+                {
+                    return Err(format!(
+                        "crop exceeds the {}x{} image",
+                        img.width(),
+                        img.height()
+                    ));
+                }
+                img.crop_imm(x, y, width, height)
+            }
+            Operation::Rotate { degrees } => match degrees % 360 {
+                90 => img.rotate90(),
+                180 => img.rotate180(),
+                270 => img.rotate270(),
+                _ => img,
+            },
+            Operation::Grayscale => img.grayscale(),
+            Operation::Blur { sigma } => img.blur(sigma),
+            Operation::Thumbnail { width, height } => {
+                if img.width() <= width && img.height() <= height {
+                    img
+                } else {
+                    img.thumbnail(width, height)
+                }
+            }
+            Operation::Convert { .. } => img,
+        })
+    }
+}
+
+fn decode_limited(
+    data: &[u8],
+    image_limits: &ImageLimits,
+) -> Result<(Option<ImageFormat>, DynamicImage), Error> {
+    let reader = || {
+        ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .context("Failed to guess format")
+    };
+    let bad_request = |err: ImageError| HttpError::new(StatusCode::BAD_REQUEST, err.to_string());
+
+    // Only the header is read here, a bomb is rejected before its pixels are allocated
+    let (width, height) = reader()?.into_dimensions().map_err(bad_request)?;
+    if width as u64 * height as u64 > image_limits.max_pixels {
+        return Err(HttpError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Image is {}x{}, more than {} pixels",
+                width, height, image_limits.max_pixels
+            ),
+        )
+        .into());
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = reader()?;
+    reader.limits(limits);
+    let format = reader.format();
+    let img = reader.decode().map_err(|err| match err {
+        ImageError::Limits(_) => HttpError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image exceeds {0}x{0} or the decode budget", MAX_DIMENSION),
+        ),
+        err => bad_request(err),
+    })?;
+
+    Ok((format, img))
+}
+
+fn encode(img: DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>, Error> {
+    let mut out = Cursor::new(Vec::new());
+    // Neither encoder accepts every colour type, JPEG has no alpha and WebP is 8 bit only
+    let result = match format {
+        OutputFormat::Png => img.write_to(&mut out, ImageOutputFormat::Png),
+        OutputFormat::Jpeg => DynamicImage::ImageRgb8(img.into_rgb8())
+            .write_to(&mut out, ImageOutputFormat::Jpeg(quality)),
+        OutputFormat::Webp => {
+            DynamicImage::ImageRgba8(img.into_rgba8()).write_to(&mut out, ImageOutputFormat::WebP)
+        }
+    };
+    result.context("Failed to encode image")?;
+
+    Ok(out.into_inner())
+}
+
+fn pipeline(
+    image: &[u8],
+    operations: &[Operation],
+    limits: &ImageLimits,
+) -> Result<(OutputFormat, Vec<u8>), Error> {
+    let (format, mut img) = decode_limited(image, limits)?;
+    let mut output = match format {
+        Some(ImageFormat::Jpeg) => OutputFormat::Jpeg,
+        Some(ImageFormat::WebP) => OutputFormat::Webp,
+        _ => OutputFormat::Png,
+    };
+    let mut quality = DEFAULT_JPEG_QUALITY;
+    for (index, operation) in operations.iter().enumerate() {
+        info!(index, ?operation);
+        if let Operation::Convert {
+            format,
+            quality: requested,
+        } = *operation
+        {
+            output = format;
+            quality = requested.unwrap_or(DEFAULT_JPEG_QUALITY);
+        }
+
+        // Checked up front, an upscale could otherwise allocate far more than the decode did
+        let (width, height) = operation.output_dimensions(img.width(), img.height());
+        if width as u64 * height as u64 > limits.max_pixels {
+            return Err(HttpError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Operation {} would produce a {}x{} image, more than {} pixels",
+                    index, width, height, limits.max_pixels
+                ),
+            )
+            .into());
+        }
+        img = operation
+            .apply(img)
+            .map_err(|message| invalid(index, "operations", message))?;
+    }
+
+    Ok((output, encode(img, output, quality)?))
+}
+
+pub async fn transform(
+    State(state): State<Arc<router::State>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Error> {
+    let mut image = None;
+    let mut operations: Vec<Operation> = Vec::new();
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .context("Failed to get multipart field")?
+    {
+        match field.name() {
+            Some("image") => image = Some(read_field(&mut field, &state.image_limits).await?),
+            Some("operations") => {
+                let json = read_field(&mut field, &state.image_limits).await?;
+                operations = serde_json::from_slice(&json).map_err(|err| {
+                    HttpError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+                })?;
+            }
+            _ => {}
+        }
+    }
+    let image =
+        image.ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "Missing `image` field"))?;
+    if operations.len() > MAX_OPERATIONS {
+        return Err(HttpError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("At most {} operations are allowed", MAX_OPERATIONS),
+        )
+        .into());
+    }
+
+    let errors = operations
+        .iter()
+        .enumerate()
+        .filter_map(|(index, operation)| {
+            operation
+                .validate()
+                .err()
+                .map(|message| FieldError::new(index, "operations", message))
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(ValidationFailed { errors }.into());
+    }
+
+    let limits = state.image_limits;
+    let (output, body) = state
+        .image_pool
+        .run(move || pipeline(&image, &operations, &limits))
+        .await?;
+    Ok(([(CONTENT_TYPE, output.content_type())], body))
+}
+
+pub async fn pool_metrics(State(state): State<Arc<router::State>>) -> impl IntoResponse {
+    Json(state.image_pool.metrics())
 }
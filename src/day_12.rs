@@ -1,18 +1,31 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Context;
 use axum::{
     extract::{self, Path, State},
+    http::StatusCode,
     response::IntoResponse,
     Json,
 };
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, SecondsFormat, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use shuttle_persist::PersistInstance;
 use tracing::info;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::router::{self, Error};
+use crate::{
+    ids,
+    pagination::Pagination,
+    router::{self, Error, HttpError},
+    validation::{invalid, ValidationFailed},
+};
 
 pub async fn task_01_save(
     Path(id): Path<String>,
@@ -29,10 +42,10 @@ pub async fn task_01_load(
     Path(id): Path<String>,
     State(state): State<Arc<router::State>>,
 ) -> Result<impl IntoResponse, Error> {
-    let previous_time = state
+    let previous_time: SystemTime = state
         .persist
         .load(&format!("day-12_{}", id))
-        .unwrap_or(SystemTime::now());
+        .map_err(|_| HttpError::not_found("Nothing saved under this id"))?;
 
     let duration = SystemTime::now()
         .duration_since(previous_time)
@@ -43,9 +56,8 @@ pub async fn task_01_load(
 pub async fn task_02(
     extract::Json(ulids): extract::Json<Vec<String>>,
 ) -> Result<impl IntoResponse, Error> {
-    let result = ulids
+    let result = ids::parse_ulids(&ulids)?
         .into_iter()
-        .filter_map(|str| Ulid::from_string(&str).ok())
         .map(|ulid| Uuid::from_u128(ulid.0))
         .rev()
         .collect::<Vec<Uuid>>();
@@ -69,69 +81,596 @@ pub async fn task_03(
     Path(day): Path<u64>,
     extract::Json(ulids): extract::Json<Vec<String>>,
 ) -> Result<impl IntoResponse, Error> {
-    let result = ulids
+    let ulids = ids::parse_ulids(&ulids)?;
+    let now = SystemTime::now();
+    let count = |predicate: UlidPredicate| {
+        ulids
+            .iter()
+            .filter(|ulid| predicate.matches(**ulid, &chrono_tz::UTC, now))
+            .count()
+    };
+
+    let weekday = u8::try_from(day)
+        .ok()
+        .and_then(|day| Weekday::try_from(day).ok());
+    let res = Response {
+        eve: count(UlidPredicate {
+            month: Some(12),
+            day: Some(24),
+            ..Default::default()
+        }),
+        day: weekday.map_or(0, |weekday| {
+            count(UlidPredicate {
+                weekday: Some(weekday),
+                ..Default::default()
+            })
+        }),
+        future: count(UlidPredicate {
+            future: Some(true),
+            ..Default::default()
+        }),
+        lsb: count(UlidPredicate {
+            lsb: Some(true),
+            ..Default::default()
+        }),
+    };
+
+    info!(?res);
+
+    Ok(Json(res))
+}
+
+/// Conditions on a ULID's timestamp, read in the query's time zone. Every
+/// field that's set has to hold.
+#[derive(Debug, Default, Deserialize)]
+pub struct UlidFilter {
+    month: Option<u32>,
+    day: Option<u32>,
+    /// `mon`, `monday` or 0 (Monday) to 6 (Sunday)
+    weekday: Option<String>,
+    /// Inclusive `YYYY-MM-DD` dates
+    from: Option<String>,
+    to: Option<String>,
+    /// Inclusive hours of the day, `[22, 2]` wraps past midnight
+    hours: Option<(u32, u32)>,
+    future: Option<bool>,
+    lsb: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+struct UlidPredicate {
+    month: Option<u32>,
+    day: Option<u32>,
+    weekday: Option<Weekday>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    hours: Option<(u32, u32)>,
+    future: Option<bool>,
+    lsb: Option<bool>,
+}
+
+impl TryFrom<UlidFilter> for UlidPredicate {
+    type Error = ValidationFailed;
+
+    fn try_from(filter: UlidFilter) -> Result<Self, Self::Error> {
+        if filter.month.is_some_and(|month| !(1..=12).contains(&month)) {
+            return Err(invalid(0, "month", "must be between 1 and 12"));
+        }
+        if filter.day.is_some_and(|day| !(1..=31).contains(&day)) {
+            return Err(invalid(0, "day", "must be between 1 and 31"));
+        }
+        if filter
+            .hours
+            .is_some_and(|(start, end)| start > 23 || end > 23)
+        {
+            return Err(invalid(0, "hours", "must be between 0 and 23"));
+        }
+
+        let weekday = filter
+            .weekday
+            .map(|weekday| match weekday.parse::<u8>() {
+                Ok(number) => Weekday::try_from(number).ok(),
+                Err(_) => weekday.parse().ok(),
+            })
+            .map(|weekday| {
+                weekday.ok_or_else(|| invalid(0, "weekday", "must be a day name or 0 to 6"))
+            })
+            .transpose()?;
+        let date = |field: &str, date: Option<String>| {
+            date.map(|date| {
+                date.parse::<NaiveDate>()
+                    .map_err(|err| invalid(0, field, format!("must be YYYY-MM-DD: {}", err)))
+            })
+            .transpose()
+        };
+        let from = date("from", filter.from)?;
+        let to = date("to", filter.to)?;
+        if from.zip(to).is_some_and(|(from, to)| from > to) {
+            return Err(invalid(0, "to", "must not be before from"));
+        }
+
+        Ok(UlidPredicate {
+            month: filter.month,
+            day: filter.day,
+            weekday,
+            from,
+            to,
+            hours: filter.hours,
+            future: filter.future,
+            lsb: filter.lsb,
+        })
+    }
+}
+
+impl UlidPredicate {
+    fn matches(&self, ulid: Ulid, tz: &Tz, now: SystemTime) -> bool {
+        let time = DateTime::<Utc>::from(ulid.datetime()).with_timezone(tz);
+        let date = time.date_naive();
+        let hour_matches = |(start, end): (u32, u32)| {
+            if start <= end {
+                (start..=end).contains(&time.hour())
+            } else {
+                time.hour() >= start || time.hour() <= end
+            }
+        };
+
+        self.month.is_none_or(|month| time.month() == month)
+            && self.day.is_none_or(|day| time.day() == day)
+            && self.weekday.is_none_or(|weekday| time.weekday() == weekday)
+            && self.from.is_none_or(|from| date >= from)
+            && self.to.is_none_or(|to| date <= to)
+            && self.hours.is_none_or(hour_matches)
+            && self
+                .future
+                .is_none_or(|future| (ulid.datetime() > now) == future)
+            && self.lsb.is_none_or(|lsb| (ulid.0 & 1 == 1) == lsb)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Day,
+    Week,
+    Month,
+}
+
+impl GroupBy {
+    fn bucket(self, time: &DateTime<Tz>) -> String {
+        match self {
+            GroupBy::Day => time.format("%Y-%m-%d").to_string(),
+            GroupBy::Week => {
+                let week = time.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            GroupBy::Month => time.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UlidQuery {
+    ulids: Vec<String>,
+    /// IANA name such as `Europe/Oslo`, defaults to UTC
+    tz: Option<String>,
+    #[serde(default)]
+    filter: UlidFilter,
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Debug, Serialize)]
+struct Bucket {
+    bucket: String,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct UlidReport {
+    total: usize,
+    matched: usize,
+    timestamps: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    histogram: Option<Vec<Bucket>>,
+}
+
+pub async fn query_ulids(
+    extract::Json(query): extract::Json<UlidQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let tz = match &query.tz {
+        Some(tz) => tz
+            .parse::<Tz>()
+            .map_err(|_| invalid(0, "tz", format!("unknown time zone `{}`", tz)))?,
+        None => chrono_tz::UTC,
+    };
+    let predicate = UlidPredicate::try_from(query.filter)?;
+    let ulids = ids::parse_ulids(&query.ulids)?;
+
+    let now = SystemTime::now();
+    let matched = ulids
+        .iter()
+        .filter(|ulid| predicate.matches(**ulid, &tz, now))
+        .map(|ulid| DateTime::<Utc>::from(ulid.datetime()).with_timezone(&tz))
+        .sorted()
+        .collect::<Vec<_>>();
+
+    let histogram = query.group_by.map(|group_by| {
+        matched
+            .iter()
+            .map(|time| group_by.bucket(time))
+            // Times are sorted, so each bucket's entries are adjacent
+            .dedup_with_count()
+            .map(|(count, bucket)| Bucket { bucket, count })
+            .collect()
+    });
+
+    let report = UlidReport {
+        total: ulids.len(),
+        matched: matched.len(),
+        timestamps: matched
+            .iter()
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true))
+            .collect(),
+        histogram,
+    };
+    info!(total = report.total, matched = report.matched, %tz);
+
+    Ok(Json(report))
+}
+
+const TIMER_PREFIX: &str = "day-12-timer_";
+const DEFAULT_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MAX_EXPIRY: Duration = Duration::from_secs(366 * 24 * 60 * 60);
+
+/// Formats as an ISO 8601 duration with millisecond precision, e.g. `P1DT2H3M4.005S`
+fn iso_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    let (days, rest) = (millis / 86_400_000, millis % 86_400_000);
+    let (hours, rest) = (rest / 3_600_000, rest % 3_600_000);
+    let (minutes, rest) = (rest / 60_000, rest % 60_000);
+    let (seconds, millis) = (rest / 1000, rest % 1000);
+
+    let mut iso = "P".to_string();
+    if days > 0 {
+        iso += &format!("{}D", days);
+    }
+    if hours + minutes + seconds + millis > 0 || days == 0 {
+        iso += "T";
+    }
+    if hours > 0 {
+        iso += &format!("{}H", hours);
+    }
+    if minutes > 0 {
+        iso += &format!("{}M", minutes);
+    }
+    if millis > 0 {
+        iso += &format!("{}.{:03}S", seconds, millis);
+    } else if seconds > 0 || iso == "PT" {
+        iso += &format!("{}S", seconds);
+    }
+    iso
+}
+
+/// Parses weeks, days, hours, minutes and fractional seconds, years and
+/// months are refused since their length depends on the calendar
+fn parse_iso_duration(iso: &str) -> Result<Duration, String> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r"^P(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+(?:[.,]\d+)?)S)?)?$")
+            .expect("Valid duration regex")
+    });
+    let invalid = || format!("`{}` is not an ISO 8601 duration like PT1H30M", iso);
+    let captures = re.captures(iso).ok_or_else(invalid)?;
+    if iso.ends_with('T') || captures.iter().skip(1).all(|group| group.is_none()) {
+        return Err(invalid());
+    }
+
+    let seconds = [7 * 86_400, 86_400, 3600, 60, 1]
         .into_iter()
-        .filter_map(|str| Ulid::from_string(&str).ok())
-        .collect::<Vec<Ulid>>();
+        .zip(captures.iter().skip(1))
+        .filter_map(|(unit, group)| Some((unit as f64, group?.as_str())))
+        .map(|(unit, value)| {
+            Ok(unit
+                * value
+                    .replace(',', ".")
+                    .parse::<f64>()
+                    .map_err(|_| invalid())?)
+        })
+        .sum::<Result<f64, String>>()?;
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Timer {
+    id: String,
+    name: Option<String>,
+    created_at: SystemTime,
+    /// Start of the running segment, `None` while paused
+    running_since: Option<SystemTime>,
+    /// Time banked by segments that have ended
+    accumulated: Duration,
+    laps: Vec<Duration>,
+    touched_at: SystemTime,
+    expiry: Duration,
+}
+
+impl Timer {
+    fn elapsed(&self, now: SystemTime) -> Duration {
+        let running = self
+            .running_since
+            .and_then(|since| now.duration_since(since).ok())
+            .unwrap_or_default();
+        self.accumulated + running
+    }
+
+    fn expires_at(&self) -> SystemTime {
+        self.touched_at + self.expiry
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LapView {
+    number: usize,
+    at: String,
+    at_ms: u128,
+    split: String,
+    split_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+struct TimerView {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    running: bool,
+    elapsed: String,
+    elapsed_ms: u128,
+    laps: Vec<LapView>,
+    created_at: String,
+    expires_at: String,
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl From<&Timer> for TimerView {
+    fn from(timer: &Timer) -> Self {
+        let elapsed = timer.elapsed(SystemTime::now());
+        let laps = timer
+            .laps
+            .iter()
+            .scan(Duration::ZERO, |previous, &at| {
+                let split = at.saturating_sub(*previous);
+                *previous = at;
+                Some((at, split))
+            })
+            .enumerate()
+            .map(|(index, (at, split))| LapView {
+                number: index + 1,
+                at: iso_duration(at),
+                at_ms: at.as_millis(),
+                split: iso_duration(split),
+                split_ms: split.as_millis(),
+            })
+            .collect();
+
+        TimerView {
+            id: timer.id.clone(),
+            name: timer.name.clone(),
+            running: timer.running_since.is_some(),
+            elapsed: iso_duration(elapsed),
+            elapsed_ms: elapsed.as_millis(),
+            laps,
+            created_at: rfc3339(timer.created_at),
+            expires_at: rfc3339(timer.expires_at()),
+        }
+    }
+}
+
+// Ids end up in file names, so they're kept to a safe alphabet
+fn timer_key(id: &str) -> Result<String, ValidationFailed> {
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(invalid(
+            0,
+            "id",
+            "must be 1 to 64 letters, digits, `-` or `_`",
+        ));
+    }
+    Ok(format!("{}{}", TIMER_PREFIX, id))
+}
+
+fn timer_not_found() -> HttpError {
+    HttpError::not_found("Timer not found")
+}
+
+/// Loads a timer, one that sat untouched past its expiry reads as missing
+/// until [`TimerStore::purge`] drops it
+fn load_timer(persist: &PersistInstance, id: &str) -> Result<Timer, Error> {
+    let key = timer_key(id)?;
+    let timer: Timer = persist.load(&key).map_err(|_| timer_not_found())?;
+    if timer.expires_at() < SystemTime::now() {
+        return Err(timer_not_found().into());
+    }
+    Ok(timer)
+}
+
+fn save_timer(persist: &PersistInstance, timer: &mut Timer) -> Result<(), Error> {
+    timer.touched_at = SystemTime::now();
+    persist
+        .save(&timer_key(&timer.id)?, &*timer)
+        .context("Failed to persist timer")?;
+    Ok(())
+}
+
+/// Serialises timer writes so concurrent pauses, resumes and laps can't drop
+/// each other's changes
+#[derive(Default)]
+pub struct TimerStore {
+    write_lock: Mutex<()>,
+}
+
+impl TimerStore {
+    fn update(
+        &self,
+        persist: &PersistInstance,
+        id: &str,
+        change: impl FnOnce(&mut Timer),
+    ) -> Result<Timer, Error> {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut timer = load_timer(persist, id)?;
+        change(&mut timer);
+        save_timer(persist, &mut timer)?;
+        Ok(timer)
+    }
+
+    // Re-read under the lock, the timer may have been recreated since it expired
+    fn purge(&self, persist: &PersistInstance, id: &str) {
+        let Ok(key) = timer_key(id) else {
+            return;
+        };
+        let _guard = self.write_lock.lock().unwrap();
+        let expired = persist
+            .load::<Timer>(&key)
+            .is_ok_and(|timer| timer.expires_at() < SystemTime::now());
+        if expired {
+            info!(id, "Timer expired");
+            let _ = persist.remove(&key);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CreateTimer {
+    id: Option<String>,
+    name: Option<String>,
+    /// ISO 8601 duration of inactivity after which the timer is dropped
+    expires_in: Option<String>,
+    #[serde(default)]
+    paused: bool,
+}
+
+pub async fn create_timer(
+    State(state): State<Arc<router::State>>,
+    Json(create): Json<CreateTimer>,
+) -> Result<impl IntoResponse, Error> {
+    let id = create.id.unwrap_or_else(|| Ulid::new().to_string());
+    timer_key(&id)?;
+    let expiry = create
+        .expires_in
+        .as_deref()
+        .map(parse_iso_duration)
+        .transpose()
+        .map_err(|message| invalid(0, "expires_in", message))?
+        .unwrap_or(DEFAULT_EXPIRY);
+    if expiry.is_zero() || expiry > MAX_EXPIRY {
+        return Err(invalid(0, "expires_in", "must be between 1 ms and 366 days").into());
+    }
+    let _guard = state.timers.write_lock.lock().unwrap();
+    if load_timer(&state.persist, &id).is_ok() {
+        return Err(HttpError::new(StatusCode::CONFLICT, "Timer already exists").into());
+    }
 
     let now = SystemTime::now();
+    let mut timer = Timer {
+        id,
+        name: create.name,
+        created_at: now,
+        running_since: (!create.paused).then_some(now),
+        accumulated: Duration::ZERO,
+        laps: Vec::new(),
+        touched_at: now,
+        expiry,
+    };
+    save_timer(&state.persist, &mut timer)?;
 
-    let future = result
-        .iter()
-        .filter(|ulid| ulid.datetime().duration_since(now).is_ok())
-        .count();
+    info!(?timer);
+    Ok((StatusCode::CREATED, Json(TimerView::from(&timer))))
+}
 
-    let day = result
+pub async fn list_timers(
+    State(state): State<Arc<router::State>>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, Error> {
+    let keys = state.persist.list().context("Failed to list timers")?;
+    let timers = keys
         .iter()
-        .filter(|ulid| {
-            let chrono_time: DateTime<Utc> = ulid.datetime().into();
-            let weekday = chrono_time.weekday();
-
-            use chrono::Weekday::*;
-            match day {
-                0 => Mon == weekday,
-                1 => Tue == weekday,
-                2 => Wed == weekday,
-                3 => Thu == weekday,
-                4 => Fri == weekday,
-                5 => Sat == weekday,
-                6 => Sun == weekday,
-                _ => false,
+        .filter_map(|key| key.strip_prefix(TIMER_PREFIX))
+        .sorted()
+        .filter_map(|id| {
+            let timer = load_timer(&state.persist, id);
+            if timer.is_err() {
+                state.timers.purge(&state.persist, id);
             }
+            timer.ok()
         })
-        .count();
+        .map(|timer| TimerView::from(&timer))
+        .collect::<Vec<_>>();
 
-    let eve = result
-        .iter()
-        .filter(|ulid| {
-            let chrono_time: DateTime<Utc> = ulid.datetime().into();
+    Ok(pagination.slice(timers))
+}
 
-            let month = chrono_time.month();
-            let day = chrono_time.day();
+pub async fn get_timer(
+    Path(id): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let timer = load_timer(&state.persist, &id).map_err(|err| {
+        state.timers.purge(&state.persist, &id);
+        err
+    })?;
+    Ok(Json(TimerView::from(&timer)))
+}
 
-            month == 12 && day == 24
-        })
-        .count();
+pub async fn delete_timer(
+    Path(id): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let _guard = state.timers.write_lock.lock().unwrap();
+    load_timer(&state.persist, &id)?;
+    state
+        .persist
+        .remove(&timer_key(&id)?)
+        .context("Failed to delete timer")?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let lsb = result
-        .iter()
-        .filter(|ulid| {
-            let bits = ulid.0;
+pub async fn pause_timer(
+    Path(id): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    // Pausing a paused timer is a no-op, so retries are safe
+    let timer = state.timers.update(&state.persist, &id, |timer| {
+        timer.accumulated = timer.elapsed(SystemTime::now());
+        timer.running_since = None;
+    })?;
 
-            let lsb = bits & 1;
+    Ok(Json(TimerView::from(&timer)))
+}
 
-            lsb == 1
-        })
-        .count();
+pub async fn resume_timer(
+    Path(id): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let timer = state.timers.update(&state.persist, &id, |timer| {
+        timer.running_since.get_or_insert_with(SystemTime::now);
+    })?;
 
-    let res = Response {
-        eve,
-        day,
-        future,
-        lsb,
-    };
+    Ok(Json(TimerView::from(&timer)))
+}
 
-    info!(?res);
+pub async fn lap_timer(
+    Path(id): Path<String>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let timer = state.timers.update(&state.persist, &id, |timer| {
+        let elapsed = timer.elapsed(SystemTime::now());
+        timer.laps.push(elapsed);
+    })?;
 
-    Ok(Json(res))
+    Ok((StatusCode::CREATED, Json(TimerView::from(&timer))))
 }
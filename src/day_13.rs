@@ -5,8 +5,13 @@ use axum::{extract::State, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use tracing::info;
+use validator::Validate;
 
-use crate::router::{self, Error};
+use crate::{
+    pagination::Pagination,
+    router::{self, Error},
+    validation::validate_all,
+};
 
 pub async fn task_01(State(state): State<Arc<router::State>>) -> Result<impl IntoResponse, Error> {
     let sql = sqlx::query_scalar!("SELECT 20231213")
@@ -39,11 +44,13 @@ pub async fn task_02_reset(
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Serialize, Deserialize, FromRow, Validate)]
 pub struct Order {
     pub id: i32,
     pub region_id: i32,
+    #[validate(length(min = 1, max = 50, message = "must be between 1 and 50 characters"))]
     pub gift_name: String,
+    #[validate(range(min = 0, message = "must not be negative"))]
     pub quantity: i32,
 }
 
@@ -51,6 +58,8 @@ pub async fn task_02_orders(
     State(state): State<Arc<router::State>>,
     Json(orders): Json<Vec<Order>>,
 ) -> Result<impl IntoResponse, Error> {
+    validate_all(&orders)?;
+
     let mut transaction = state
         .pool
         .begin()
@@ -80,6 +89,39 @@ pub async fn task_02_orders(
     Ok(())
 }
 
+pub async fn list_orders(
+    pagination: Pagination,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let total = sqlx::query_scalar!("SELECT COUNT(*) FROM orders")
+        .fetch_one(&state.pool)
+        .await
+        .context("Failed to count orders")?
+        .unwrap_or(0);
+
+    let orders = sqlx::query_as!(
+        Order,
+        r#"
+        SELECT
+            id,
+            region_id AS "region_id!",
+            gift_name AS "gift_name!",
+            quantity AS "quantity!"
+        FROM orders
+        ORDER BY id
+        LIMIT $1
+        OFFSET $2
+        "#,
+        pagination.limit().map(|limit| limit as i64),
+        pagination.offset() as i64
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to select orders")?;
+
+    Ok(pagination.page(orders, total as usize))
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Total {
     total: i64,
@@ -9,10 +9,13 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use tracing::info;
+use validator::Validate;
 
 use crate::{
     day_13::Order,
+    pagination::Pagination,
     router::{self, Error},
+    validation::validate_all,
 };
 
 pub async fn task_01_reset(
@@ -51,6 +54,8 @@ pub async fn task_01_orders(
     State(state): State<Arc<router::State>>,
     Json(orders): Json<Vec<Order>>,
 ) -> Result<impl IntoResponse, Error> {
+    validate_all(&orders)?;
+
     let mut transaction = state
         .pool
         .begin()
@@ -90,9 +95,10 @@ pub async fn task_01_orders(
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct Region {
     id: i32,
+    #[validate(length(min = 1, max = 50, message = "must be between 1 and 50 characters"))]
     name: String,
 }
 
@@ -100,6 +106,8 @@ pub async fn task_01_regions(
     State(state): State<Arc<router::State>>,
     Json(regions): Json<Vec<Region>>,
 ) -> Result<impl IntoResponse, Error> {
+    validate_all(&regions)?;
+
     let mut transaction = state
         .pool
         .begin()
@@ -133,6 +141,37 @@ pub async fn task_01_regions(
     Ok(())
 }
 
+pub async fn list_regions(
+    pagination: Pagination,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let total = sqlx::query_scalar!("SELECT COUNT(*) FROM regions")
+        .fetch_one(&state.pool)
+        .await
+        .context("Failed to count regions")?
+        .unwrap_or(0);
+
+    let regions = sqlx::query_as!(
+        Region,
+        r#"
+        SELECT
+            id,
+            name AS "name!"
+        FROM regions
+        ORDER BY id
+        LIMIT $1
+        OFFSET $2
+        "#,
+        pagination.limit().map(|limit| limit as i64),
+        pagination.offset() as i64
+    )
+    .fetch_all(&state.pool)
+    .await
+    .context("Failed to select regions")?;
+
+    Ok(pagination.page(regions, total as usize))
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct RegionResult {
     #[serde(rename = "region")]
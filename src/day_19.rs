@@ -1,5 +1,5 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, VecDeque},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -27,7 +27,7 @@ use tokio::sync::{
 };
 use tracing::{info, warn};
 
-use crate::router::Error;
+use crate::{pagination::Pagination, router::Error};
 
 async fn task_01_ping(ws: WebSocketUpgrade) -> Result<impl IntoResponse, Error> {
     info!("Created ping socket");
@@ -61,10 +61,13 @@ async fn handle_socket(mut socket: WebSocket) {
     }
 }
 
+type History = Arc<Mutex<HashMap<usize, VecDeque<ChatTx>>>>;
+
 #[derive(Clone)]
 struct BirdState {
     views: Arc<AtomicU64>,
     rooms: Arc<Mutex<HashMap<usize, Sender<ChatTx>>>>,
+    history: History,
 }
 
 async fn task_02_reset(State(state): State<BirdState>) -> Result<impl IntoResponse, Error> {
@@ -103,7 +106,7 @@ async fn task_02_room(
         }
     };
 
-    Ok(ws.on_upgrade(move |ws| room_handler(ws, tx, rx, name, state.views.clone())))
+    Ok(ws.on_upgrade(move |ws| room_handler(ws, tx, rx, name, number, state)))
 }
 
 async fn room_handler(
@@ -111,12 +114,33 @@ async fn room_handler(
     tx: Sender<ChatTx>,
     rx: Receiver<ChatTx>,
     name: Arc<str>,
-    views: Arc<AtomicU64>,
+    number: usize,
+    state: BirdState,
 ) {
     let (sender, receiver) = ws.split();
 
-    tokio::spawn(tx_handler(receiver, tx, name));
-    tokio::spawn(rx_handler(sender, rx, views));
+    tokio::spawn(tx_handler(receiver, tx, name, number, state.history));
+    tokio::spawn(rx_handler(sender, rx, state.views));
+}
+
+// Enough for a client to catch up on everything a room could have broadcast
+const HISTORY_CAPACITY: usize = BROADCAST_CAPACITY;
+
+async fn task_02_history(
+    Path(number): Path<usize>,
+    State(state): State<BirdState>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, Error> {
+    let history = state
+        .history
+        .lock()
+        .await
+        .get(&number)
+        .map(|messages| messages.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    info!(?number, messages = history.len());
+
+    Ok(pagination.slice(history))
 }
 
 #[derive(Deserialize)]
@@ -130,7 +154,13 @@ struct ChatTx {
     message: Arc<str>,
 }
 
-async fn tx_handler(mut ws_receiver: SplitStream<WebSocket>, tx: Sender<ChatTx>, name: Arc<str>) {
+async fn tx_handler(
+    mut ws_receiver: SplitStream<WebSocket>,
+    tx: Sender<ChatTx>,
+    name: Arc<str>,
+    number: usize,
+    history: History,
+) {
     while let Some(Ok(Message::Text(text))) = ws_receiver.next().await {
         if let Ok(chat) = serde_json::from_str::<ChatRx>(&text) {
             if chat.message.len() <= 128 {
@@ -140,6 +170,15 @@ async fn tx_handler(mut ws_receiver: SplitStream<WebSocket>, tx: Sender<ChatTx>,
                 };
                 info!(?tx_message);
 
+                {
+                    let mut history = history.lock().await;
+                    let messages = history.entry(number).or_default();
+                    if messages.len() == HISTORY_CAPACITY {
+                        messages.pop_front();
+                    }
+                    messages.push_back(tx_message.clone());
+                }
+
                 if let Err(e) = tx.send(tx_message) {
                     warn!("Failed to transmit message: {:?}", e);
                 }
@@ -171,6 +210,7 @@ pub fn router() -> Router {
     let state = BirdState {
         views: Arc::new(AtomicU64::new(0)),
         rooms: Arc::new(Mutex::new(HashMap::new())),
+        history: Arc::new(Mutex::new(HashMap::new())),
     };
 
     Router::new()
@@ -178,6 +218,6 @@ pub fn router() -> Router {
         .route("/reset", post(task_02_reset))
         .route("/views", get(task_02_views))
         .route("/ws/room/:number/user/:name", get(task_02_room))
+        .route("/room/:number/history", get(task_02_history))
         .with_state(state)
 }
-
@@ -0,0 +1,451 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{self, Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use ulid::{Generator, Ulid};
+use uuid::{timestamp::UUID_TICKS_BETWEEN_EPOCHS, Uuid, Variant};
+
+use crate::{
+    router::{self, Error},
+    validation::{invalid, FieldError, ValidationFailed},
+};
+
+const MAX_GENERATE: usize = 1000;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Hands out ULIDs that keep increasing even within the same millisecond
+pub struct UlidGenerator(Mutex<Generator>);
+
+impl Default for UlidGenerator {
+    fn default() -> Self {
+        UlidGenerator(Mutex::new(Generator::new()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Ulid,
+    UuidV4,
+    UuidV7,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateOptions {
+    kind: Kind,
+    #[serde(default = "default_count")]
+    count: usize,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+pub async fn generate(
+    State(state): State<Arc<router::State>>,
+    Query(options): Query<GenerateOptions>,
+) -> Result<impl IntoResponse, Error> {
+    if !(1..=MAX_GENERATE).contains(&options.count) {
+        return Err(invalid(
+            0,
+            "count",
+            format!("must be between 1 and {}", MAX_GENERATE),
+        )
+        .into());
+    }
+
+    let ids = match options.kind {
+        Kind::Ulid => {
+            let mut generator = state.ulid_generator.0.lock().unwrap();
+            (0..options.count)
+                .map(|_| generator.generate().map(|ulid| ulid.to_string()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| anyhow::anyhow!("Failed to generate ulid: {}", err))?
+        }
+        Kind::UuidV4 => (0..options.count)
+            .map(|_| Uuid::new_v4().to_string())
+            .collect(),
+        Kind::UuidV7 => (0..options.count)
+            .map(|_| Uuid::now_v7().to_string())
+            .collect(),
+    };
+
+    info!(kind = ?options.kind, count = ids.len());
+    Ok(Json(ids))
+}
+
+/// Every representation is the same 128 bits, `base32` is RFC 4648 while
+/// `ulid` uses Crockford's alphabet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Ulid,
+    Uuid,
+    Int,
+    Hex,
+    Base32,
+}
+
+impl Format {
+    // RFC 4648 base32 is never guessed, and 32 digits read as hex rather than decimal
+    fn detect(input: &str) -> Result<Self, String> {
+        let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+        match input.len() {
+            36 => Ok(Format::Uuid),
+            26 => Ok(Format::Ulid),
+            _ if input.starts_with("0x") && is_hex(&input[2..]) => Ok(Format::Hex),
+            32 if is_hex(input) => Ok(Format::Hex),
+            _ if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) => Ok(Format::Int),
+            _ => Err("unrecognised identifier format".to_string()),
+        }
+    }
+
+    fn parse(self, input: &str) -> Result<u128, String> {
+        match self {
+            Format::Ulid => Ulid::from_string(input)
+                .map(|ulid| ulid.0)
+                .map_err(|err| format!("invalid ulid: {}", err)),
+            Format::Uuid => Uuid::parse_str(input)
+                .map(|uuid| uuid.as_u128())
+                .map_err(|err| format!("invalid uuid: {}", err)),
+            Format::Int => input
+                .parse()
+                .map_err(|err| format!("invalid integer: {}", err)),
+            Format::Hex => u128::from_str_radix(input.strip_prefix("0x").unwrap_or(input), 16)
+                .map_err(|err| format!("invalid hex: {}", err)),
+            Format::Base32 => base32_decode(input),
+        }
+    }
+
+    fn format(self, bits: u128) -> String {
+        match self {
+            Format::Ulid => Ulid(bits).to_string(),
+            Format::Uuid => Uuid::from_u128(bits).to_string(),
+            Format::Int => bits.to_string(),
+            Format::Hex => format!("{:032x}", bits),
+            Format::Base32 => base32_encode(bits),
+        }
+    }
+}
+
+fn base32_encode(bits: u128) -> String {
+    // 26 characters hold 130 bits, the last two are zero padding
+    (0..26)
+        .map(|i: i32| {
+            let shift = 123 - 5 * i;
+            let index = if shift >= 0 {
+                bits >> shift
+            } else {
+                bits << -shift
+            };
+            BASE32_ALPHABET[(index & 31) as usize] as char
+        })
+        .collect()
+}
+
+fn base32_decode(input: &str) -> Result<u128, String> {
+    let input = input.trim_end_matches('=');
+    if input.len() != 26 {
+        return Err(format!(
+            "invalid base32: expected 26 characters, got {}",
+            input.len()
+        ));
+    }
+
+    let mut bits = 0u128;
+    for (i, c) in input.chars().enumerate() {
+        let digit = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())
+            .ok_or_else(|| format!("invalid base32: unexpected `{}` at {}", c, i))?
+            as u128;
+        bits = match i {
+            25 if digit & 3 != 0 => return Err("invalid base32: non-zero padding bits".to_string()),
+            25 => (bits << 3) | (digit >> 2),
+            _ => (bits << 5) | digit,
+        };
+    }
+    Ok(bits)
+}
+
+fn parse(input: &str, from: Option<Format>) -> Result<u128, String> {
+    let input = input.trim();
+    let format = match from {
+        Some(format) => format,
+        None => Format::detect(input)?,
+    };
+    format.parse(input)
+}
+
+/// Parses every ULID, reporting each one that fails rather than dropping it
+pub fn parse_ulids(ulids: &[String]) -> Result<Vec<Ulid>, ValidationFailed> {
+    let mut parsed = Vec::with_capacity(ulids.len());
+    let mut errors = Vec::new();
+    for (index, ulid) in ulids.iter().enumerate() {
+        match Format::Ulid.parse(ulid) {
+            Ok(bits) => parsed.push(Ulid(bits)),
+            Err(message) => errors.push(FieldError::new(index, "ulid", message)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(ValidationFailed { errors });
+    }
+    Ok(parsed)
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConvertOptions {
+    /// Detected per item when missing
+    from: Option<Format>,
+    to: Format,
+}
+
+#[derive(Debug, Serialize)]
+struct Converted {
+    input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+pub async fn convert(
+    Query(options): Query<ConvertOptions>,
+    extract::Json(inputs): extract::Json<Vec<String>>,
+) -> Result<impl IntoResponse, Error> {
+    let converted = inputs
+        .into_iter()
+        .map(|input| {
+            let (output, error) = match parse(&input, options.from) {
+                Ok(bits) => (Some(options.to.format(bits)), None),
+                Err(error) => (None, Some(error)),
+            };
+            Converted {
+                input,
+                output,
+                error,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(converted))
+}
+
+#[derive(Debug, Serialize)]
+struct Representations {
+    ulid: String,
+    uuid: String,
+    int: String,
+    hex: String,
+    base32: String,
+    /// The leading 48 bits read as milliseconds, as in a ULID or UUIDv7
+    ulid_timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid_version: Option<usize>,
+    /// Only for UUID versions that embed a time (1, 6 and 7)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid_timestamp: Option<String>,
+}
+
+impl From<u128> for Representations {
+    fn from(bits: u128) -> Self {
+        let uuid = Uuid::from_u128(bits);
+        let uuid_version = (uuid.get_variant() == Variant::RFC4122)
+            .then(|| uuid.get_version_num())
+            .filter(|version| (1..=8).contains(version));
+        // v1 and v6 times before 1970 wrap in `to_unix`, while `to_gregorian` gives back
+        // the raw ticks, so those are left out
+        let uuid_timestamp = uuid_version
+            .and(uuid.get_timestamp())
+            .filter(|timestamp| {
+                uuid_version == Some(7) || timestamp.to_gregorian().0 >= UUID_TICKS_BETWEEN_EPOCHS
+            })
+            .map(|timestamp| {
+                let (secs, nanos) = timestamp.to_unix();
+                rfc3339(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+            });
+
+        Representations {
+            ulid: Format::Ulid.format(bits),
+            uuid: Format::Uuid.format(bits),
+            int: Format::Int.format(bits),
+            hex: Format::Hex.format(bits),
+            base32: Format::Base32.format(bits),
+            ulid_timestamp: rfc3339(Ulid(bits).datetime()),
+            uuid_version,
+            uuid_timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Inspected {
+    input: String,
+    #[serde(flatten)]
+    representations: Option<Representations>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InspectOptions {
+    from: Option<Format>,
+}
+
+pub async fn inspect(
+    Query(options): Query<InspectOptions>,
+    extract::Json(inputs): extract::Json<Vec<String>>,
+) -> Result<impl IntoResponse, Error> {
+    let inspected = inputs
+        .into_iter()
+        .map(|input| {
+            let (representations, error) = match parse(&input, options.from) {
+                Ok(bits) => (Some(bits.into()), None),
+                Err(error) => (None, Some(error)),
+            };
+            Inspected {
+                input,
+                representations,
+                error,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(inspected))
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationReport {
+    valid: usize,
+    invalid: usize,
+    errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateOptions {
+    from: Option<Format>,
+}
+
+pub async fn validate(
+    Query(options): Query<ValidateOptions>,
+    extract::Json(inputs): extract::Json<Vec<String>>,
+) -> Result<impl IntoResponse, Error> {
+    let errors = inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, input)| {
+            let message = parse(input, options.from).err()?;
+            Some(FieldError::new(index, "id", message))
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(ValidationReport {
+        valid: inputs.len() - errors.len(),
+        invalid: errors.len(),
+        errors,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_round_trips_every_bit() {
+        for bits in [0, 1, u128::MAX, 0x0123_4567_89ab_cdef_0123_4567_89ab_cdef] {
+            let encoded = base32_encode(bits);
+            assert_eq!(encoded.len(), 26);
+            assert_eq!(base32_decode(&encoded), Ok(bits));
+        }
+        assert_eq!(base32_decode("AAAAAAAAAAAAAAAAAAAAAAAAAE"), Ok(1));
+        assert_eq!(base32_decode("77777777777777777777777774"), Ok(u128::MAX));
+        // Lowercase and `=` padding are accepted
+        assert_eq!(base32_decode("aaaaaaaaaaaaaaaaaaaaaaaaae======"), Ok(1));
+    }
+
+    #[test]
+    fn base32_rejects_padding_bits_and_bad_input() {
+        // The last character carries 3 bits, its low 2 bits are padding and must be zero
+        for last in ['B', 'C', 'D', '7'] {
+            let input = format!("{}{}", "A".repeat(25), last);
+            assert_eq!(
+                base32_decode(&input).unwrap_err(),
+                "invalid base32: non-zero padding bits"
+            );
+        }
+        assert!(base32_decode(&"A".repeat(25)).is_err());
+        assert!(base32_decode(&"A".repeat(27)).is_err());
+        assert!(base32_decode(&format!("{}1", "A".repeat(25))).is_err());
+    }
+
+    #[test]
+    fn detects_formats_by_shape() {
+        let detect = |input: &str| Format::detect(input);
+        assert_eq!(detect("01BRZ3NDEKTSV4RRFFQ69G5FAV"), Ok(Format::Ulid));
+        assert_eq!(
+            detect("0188bfa1-4e1b-7ab3-9d5f-3b2d1e0c4a11"),
+            Ok(Format::Uuid)
+        );
+        assert_eq!(detect("0xff"), Ok(Format::Hex));
+        assert_eq!(detect("12345"), Ok(Format::Int));
+        // 32 characters read as hex even when they are all digits
+        assert_eq!(detect(&"1".repeat(32)), Ok(Format::Hex));
+        assert_eq!(detect(&"a".repeat(32)), Ok(Format::Hex));
+        assert_eq!(detect(&"1".repeat(31)), Ok(Format::Int));
+        assert_eq!(detect(&"1".repeat(33)), Ok(Format::Int));
+        assert!(detect(&"g".repeat(32)).is_err());
+        assert!(detect("0x").is_err());
+        assert!(detect("").is_err());
+        assert!(detect("nope").is_err());
+    }
+
+    #[test]
+    fn parses_what_it_detects() {
+        assert_eq!(
+            parse(&"1".repeat(32), None),
+            Ok(0x1111_1111_1111_1111_1111_1111_1111_1111)
+        );
+        assert_eq!(
+            parse(&"1".repeat(32), Some(Format::Int)),
+            Ok("1".repeat(32).parse().unwrap())
+        );
+        assert_eq!(parse(" 0xff ", None), Ok(255));
+        assert!(parse(&"9".repeat(40), None).is_err());
+    }
+
+    #[test]
+    fn leaves_out_uuid_times_before_1970() {
+        let timestamp = |uuid: &str| {
+            Representations::from(Uuid::parse_str(uuid).unwrap().as_u128()).uuid_timestamp
+        };
+        assert_eq!(
+            timestamp("c232ab00-9414-11ec-b3c8-9f6bdeced846").as_deref(),
+            Some("2022-02-22T19:22:22.000Z")
+        );
+        assert_eq!(
+            timestamp("1b21dd21-3814-6000-8000-000000000000").as_deref(),
+            Some("1970-01-01T00:00:00.000Z")
+        );
+        assert_eq!(timestamp("1b21dd21-3813-6000-8000-000000000000"), None);
+        assert_eq!(timestamp("00000000-0000-1000-8000-000000000000"), None);
+        assert_eq!(
+            timestamp("0188bfa1-4e1b-7ab3-9d5f-3b2d1e0c4a11").as_deref(),
+            Some("2023-06-15T15:16:39.579Z")
+        );
+        assert_eq!(timestamp("0188bfa1-4e1b-4ab3-9d5f-3b2d1e0c4a11"), None);
+    }
+}
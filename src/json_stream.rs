@@ -0,0 +1,248 @@
+use anyhow::Context;
+use axum::{body::Body, http::StatusCode};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+
+use crate::router::{Error, HttpError};
+
+// Largest single array element we are willing to buffer
+const MAX_ITEM_SIZE: usize = 64 * 1024;
+
+/// Splits a top level JSON array into its elements as bytes arrive, only ever
+/// holding the element currently being read.
+#[derive(Default)]
+struct ArraySplitter {
+    item: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    started: bool,
+    finished: bool,
+    /// Whitespace ended the current element, only a separator may follow
+    closed: bool,
+    seen_comma: bool,
+}
+
+fn bad_request(message: impl Into<String>) -> HttpError {
+    HttpError::new(StatusCode::BAD_REQUEST, message)
+}
+
+impl ArraySplitter {
+    fn push(
+        &mut self,
+        chunk: &[u8],
+        mut on_item: impl FnMut(&[u8]) -> Result<(), HttpError>,
+    ) -> Result<(), HttpError> {
+        for &byte in chunk {
+            if self.finished {
+                if !byte.is_ascii_whitespace() {
+                    return Err(bad_request("Unexpected data after array"));
+                }
+                continue;
+            }
+
+            if !self.started {
+                match byte {
+                    b'[' => self.started = true,
+                    byte if byte.is_ascii_whitespace() => {}
+                    _ => return Err(bad_request("Expected a JSON array")),
+                }
+                continue;
+            }
+
+            if self.in_string {
+                self.item.push(byte);
+                match (self.escaped, byte) {
+                    (true, _) => self.escaped = false,
+                    (false, b'\\') => self.escaped = true,
+                    (false, b'"') => self.in_string = false,
+                    _ => {}
+                }
+            } else {
+                if self.depth == 0
+                    && self.closed
+                    && !matches!(byte, b',' | b']')
+                    && !byte.is_ascii_whitespace()
+                {
+                    return Err(bad_request("Missing comma between array elements"));
+                }
+
+                match byte {
+                    b'"' => {
+                        self.in_string = true;
+                        self.item.push(byte);
+                    }
+                    b'{' | b'[' => {
+                        self.depth += 1;
+                        self.item.push(byte);
+                    }
+                    b'}' | b']' if self.depth > 0 => {
+                        self.depth -= 1;
+                        self.item.push(byte);
+                    }
+                    b',' if self.depth == 0 => {
+                        self.flush(&mut on_item, false)?;
+                        self.seen_comma = true;
+                    }
+                    b']' => {
+                        if self.item.is_empty() && self.seen_comma {
+                            return Err(bad_request("Trailing comma in array"));
+                        }
+                        self.flush(&mut on_item, true)?;
+                        self.finished = true;
+                    }
+                    byte if byte.is_ascii_whitespace() && self.depth == 0 => {
+                        self.closed = !self.item.is_empty();
+                    }
+                    byte => self.item.push(byte),
+                }
+            }
+
+            if self.item.len() > MAX_ITEM_SIZE {
+                return Err(HttpError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Array element exceeds {} bytes", MAX_ITEM_SIZE),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(
+        &mut self,
+        on_item: &mut impl FnMut(&[u8]) -> Result<(), HttpError>,
+        closing: bool,
+    ) -> Result<(), HttpError> {
+        // `[]` is the only place an empty element is allowed
+        if self.item.is_empty() {
+            return if closing {
+                Ok(())
+            } else {
+                Err(bad_request("Empty array element"))
+            };
+        }
+        on_item(&self.item)?;
+        self.item.clear();
+        self.closed = false;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), HttpError> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(bad_request("Unterminated JSON array"))
+        }
+    }
+}
+
+/// Deserializes each element of a streamed JSON array body and hands it to `f`,
+/// returning the number of elements seen.
+pub async fn for_each<T: DeserializeOwned>(
+    body: Body,
+    mut f: impl FnMut(T),
+) -> Result<usize, Error> {
+    let mut stream = body.into_data_stream();
+    let mut splitter = ArraySplitter::default();
+    let mut count = 0;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.context("Failed to read body chunk")?;
+        splitter.push(&chunk, |item| {
+            let value = serde_json::from_slice(item)
+                .map_err(|err| bad_request(format!("Invalid array element {}: {}", count, err)))?;
+            f(value);
+            count += 1;
+            Ok(())
+        })?;
+    }
+    splitter.finish()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `input` in chunks of `size` bytes so elements get split across pushes
+    fn split(input: &str, size: usize) -> Result<Vec<String>, String> {
+        let mut splitter = ArraySplitter::default();
+        let mut items = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            splitter
+                .push(chunk, |item| {
+                    items.push(String::from_utf8(item.to_vec()).unwrap());
+                    Ok(())
+                })
+                .map_err(|err| err.to_string())?;
+        }
+        splitter.finish().map_err(|err| err.to_string())?;
+        Ok(items)
+    }
+
+    #[test]
+    fn splits_elements_across_chunks() {
+        for size in [1, 3, usize::MAX] {
+            assert_eq!(split("[1,2, 3 ]", size).unwrap(), ["1", "2", "3"]);
+            assert_eq!(
+                split(r#" [{"a": [1, 2]}, [[]], "x"] "#, size).unwrap(),
+                [r#"{"a": [1, 2]}"#, "[[]]", r#""x""#]
+            );
+            assert!(split("[]", size).unwrap().is_empty());
+            assert!(split("[ ]", size).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn keeps_separators_inside_strings() {
+        assert_eq!(
+            split(r#"["a, b]", "c \"quoted\" d", "back\\", "{"]"#, 2).unwrap(),
+            [r#""a, b]""#, r#""c \"quoted\" d""#, r#""back\\""#, r#""{""#]
+        );
+        assert_eq!(
+            split(r#"[{"k": "v ] , \" }"}]"#, 1).unwrap(),
+            [r#"{"k": "v ] , \" }"}"#]
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_trailing_commas() {
+        assert_eq!(
+            split("[1 2]", 1).unwrap_err(),
+            "Missing comma between array elements"
+        );
+        assert_eq!(
+            split(r#"["a" "b"]"#, 4).unwrap_err(),
+            "Missing comma between array elements"
+        );
+        assert_eq!(
+            split("[{} {}]", 1).unwrap_err(),
+            "Missing comma between array elements"
+        );
+        assert_eq!(split("[1,]", 1).unwrap_err(), "Trailing comma in array");
+        assert_eq!(split("[1, ]", 1).unwrap_err(), "Trailing comma in array");
+        assert_eq!(split("[1,,2]", 1).unwrap_err(), "Empty array element");
+        assert_eq!(split("[,]", 1).unwrap_err(), "Empty array element");
+    }
+
+    #[test]
+    fn rejects_anything_but_a_single_array() {
+        assert_eq!(
+            split(r#"{"a": 1}"#, 1).unwrap_err(),
+            "Expected a JSON array"
+        );
+        assert_eq!(split("[1, 2", 1).unwrap_err(), "Unterminated JSON array");
+        assert_eq!(split("", 1).unwrap_err(), "Unterminated JSON array");
+        assert_eq!(
+            split("[1] [2]", 1).unwrap_err(),
+            "Unexpected data after array"
+        );
+        let large = format!("[\"{}\"]", "x".repeat(MAX_ITEM_SIZE));
+        assert_eq!(
+            split(&large, 4096).unwrap_err(),
+            format!("Array element exceeds {} bytes", MAX_ITEM_SIZE)
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use shuttle_persist::PersistInstance;
+use shuttle_runtime::SecretStore;
+use tracing::info;
+
+use crate::{
+    pagination::Pagination,
+    router::{self, secret, Error, HttpError},
+    validation::{invalid, ValidationFailed},
+};
+
+const KEY_PREFIX: &str = "kv_";
+// Outside `KEY_PREFIX` so it never shows up as a key
+const VERSION_KEY: &str = "kv-version";
+// A century, anything longer might as well not expire
+const MAX_TTL_SECS: u64 = 100 * 365 * 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    // Kept as JSON text, persist's binary format can't hold an untyped `Value`
+    value: String,
+    version: u64,
+    updated_at: SystemTime,
+    expires_at: Option<SystemTime>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Namespaced JSON values on top of persist. Writes are serialised through a
+/// lock so compare-and-swap can't interleave with another write.
+pub struct KvStore {
+    max_value_bytes: usize,
+    write_lock: Mutex<()>,
+}
+
+impl KvStore {
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        KvStore {
+            max_value_bytes: secret(secrets, "KV_MAX_VALUE_BYTES").unwrap_or(64 * 1024),
+            write_lock: Mutex::default(),
+        }
+    }
+
+    // Versions come from one counter for the whole store, so a key that is deleted and
+    // written again never reuses a version a client may still hold. Callers hold `write_lock`.
+    fn next_version(
+        &self,
+        persist: &PersistInstance,
+        current: Option<&Entry>,
+    ) -> Result<u64, Error> {
+        let last = persist.load::<u64>(VERSION_KEY).unwrap_or_default();
+        let version = last.max(current.map_or(0, |current| current.version)) + 1;
+        persist
+            .save(VERSION_KEY, &version)
+            .context("Failed to persist version")?;
+        Ok(version)
+    }
+
+    // Re-read under the lock, a write may have replaced the expired entry since
+    fn purge(&self, persist: &PersistInstance, storage_key: &str) {
+        let _guard = self.write_lock.lock().unwrap();
+        let expired = persist
+            .load::<Entry>(storage_key)
+            .is_ok_and(|entry| entry.is_expired(SystemTime::now()));
+        if expired {
+            let _ = persist.remove(storage_key);
+        }
+    }
+}
+
+// Namespaces can't contain `_`, so the first one after the prefix always ends the namespace
+fn storage_key(namespace: &str, key: &str) -> Result<String, ValidationFailed> {
+    let namespace_valid = !namespace.is_empty()
+        && namespace.len() <= 32
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !namespace_valid {
+        return Err(invalid(
+            0,
+            "namespace",
+            "must be 1 to 32 letters, digits or `-`",
+        ));
+    }
+
+    let key_valid = key.len() <= 128
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !key_valid {
+        return Err(invalid(
+            0,
+            "key",
+            "must be at most 128 letters, digits, `-`, `_` or `.`",
+        ));
+    }
+
+    Ok(format!("{}{}_{}", KEY_PREFIX, namespace, key))
+}
+
+/// Expired entries read as missing, only [`KvStore::purge`] removes them
+fn load(persist: &PersistInstance, storage_key: &str) -> Option<Entry> {
+    persist
+        .load::<Entry>(storage_key)
+        .ok()
+        .filter(|entry| !entry.is_expired(SystemTime::now()))
+}
+
+fn etag(version: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", version)).expect("Version is a valid header")
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[derive(Debug, Serialize)]
+struct EntryView {
+    key: String,
+    value: Box<RawValue>,
+    version: u64,
+    updated_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+}
+
+impl EntryView {
+    fn new(key: String, entry: Entry) -> Result<Self, Error> {
+        Ok(EntryView {
+            key,
+            value: RawValue::from_string(entry.value).context("Stored value is not json")?,
+            version: entry.version,
+            updated_at: rfc3339(entry.updated_at),
+            expires_at: entry.expires_at.map(rfc3339),
+        })
+    }
+}
+
+enum Precondition {
+    None,
+    /// `If-None-Match: *`, only create
+    Absent,
+    /// `If-Match: *`, only replace
+    Present,
+    /// `If-Match: "<version>"`, only replace that version
+    Version(u64),
+}
+
+impl TryFrom<&HeaderMap> for Precondition {
+    type Error = HttpError;
+
+    fn try_from(headers: &HeaderMap) -> Result<Self, Self::Error> {
+        if let Some(value) = headers.get(IF_MATCH) {
+            if value == "*" {
+                return Ok(Precondition::Present);
+            }
+            let version = value
+                .to_str()
+                .ok()
+                .map(|value| value.trim().trim_matches('"'))
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| {
+                    HttpError::new(StatusCode::BAD_REQUEST, "If-Match must be a version")
+                })?;
+            return Ok(Precondition::Version(version));
+        }
+        match headers.get(IF_NONE_MATCH) {
+            Some(value) if value == "*" => Ok(Precondition::Absent),
+            Some(_) => Err(HttpError::new(
+                StatusCode::BAD_REQUEST,
+                "If-None-Match only supports `*`",
+            )),
+            None => Ok(Precondition::None),
+        }
+    }
+}
+
+impl Precondition {
+    fn check(&self, current: Option<&Entry>) -> Result<(), HttpError> {
+        let holds = match (self, current) {
+            (Precondition::None, _) => true,
+            (Precondition::Absent, current) => current.is_none(),
+            (Precondition::Present, current) => current.is_some(),
+            (Precondition::Version(version), Some(current)) => current.version == *version,
+            (Precondition::Version(_), None) => false,
+        };
+        if !holds {
+            return Err(HttpError::new(
+                StatusCode::PRECONDITION_FAILED,
+                "Version does not match",
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub async fn get(
+    Path((namespace, key)): Path<(String, String)>,
+    State(state): State<Arc<router::State>>,
+) -> Result<impl IntoResponse, Error> {
+    let storage_key = storage_key(&namespace, &key)?;
+    let Some(entry) = load(&state.persist, &storage_key) else {
+        state.kv.purge(&state.persist, &storage_key);
+        return Err(HttpError::not_found("Key not found").into());
+    };
+
+    let version = entry.version;
+    Ok(([(ETAG, etag(version))], Json(EntryView::new(key, entry)?)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutOptions {
+    /// Seconds until the value expires, it lives forever without one
+    ttl: Option<u64>,
+}
+
+pub async fn put(
+    Path((namespace, key)): Path<(String, String)>,
+    State(state): State<Arc<router::State>>,
+    Query(options): Query<PutOptions>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, Error> {
+    let storage_key = storage_key(&namespace, &key)?;
+    let precondition = Precondition::try_from(&headers)?;
+    if body.len() > state.kv.max_value_bytes {
+        return Err(HttpError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Values are limited to {} bytes", state.kv.max_value_bytes),
+        )
+        .into());
+    }
+    let value = serde_json::from_slice::<Box<RawValue>>(&body)
+        .map_err(|err| invalid(0, "value", format!("is not json: {}", err)))?;
+    if options
+        .ttl
+        .is_some_and(|ttl| !(1..=MAX_TTL_SECS).contains(&ttl))
+    {
+        return Err(invalid(
+            0,
+            "ttl",
+            format!("must be between 1 and {} seconds", MAX_TTL_SECS),
+        )
+        .into());
+    }
+
+    let now = SystemTime::now();
+    let (status, entry) = {
+        let _guard = state.kv.write_lock.lock().unwrap();
+        let current = load(&state.persist, &storage_key);
+        precondition.check(current.as_ref())?;
+
+        let entry = Entry {
+            value: value.get().to_string(),
+            version: state.kv.next_version(&state.persist, current.as_ref())?,
+            updated_at: now,
+            expires_at: options.ttl.map(|ttl| now + Duration::from_secs(ttl)),
+        };
+        state
+            .persist
+            .save(&storage_key, &entry)
+            .context("Failed to persist value")?;
+
+        let status = match current {
+            Some(_) => StatusCode::OK,
+            None => StatusCode::CREATED,
+        };
+        (status, entry)
+    };
+
+    info!(namespace, key, version = entry.version, "Stored value");
+    let version = entry.version;
+    Ok((
+        status,
+        [(ETAG, etag(version))],
+        Json(EntryView::new(key, entry)?),
+    ))
+}
+
+pub async fn delete(
+    Path((namespace, key)): Path<(String, String)>,
+    State(state): State<Arc<router::State>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let storage_key = storage_key(&namespace, &key)?;
+    let precondition = Precondition::try_from(&headers)?;
+
+    let _guard = state.kv.write_lock.lock().unwrap();
+    let current =
+        load(&state.persist, &storage_key).ok_or_else(|| HttpError::not_found("Key not found"))?;
+    precondition.check(Some(&current))?;
+    state
+        .persist
+        .remove(&storage_key)
+        .context("Failed to delete value")?;
+
+    info!(namespace, key, "Deleted value");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListOptions {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyView {
+    key: String,
+    version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+}
+
+pub async fn list(
+    Path(namespace): Path<String>,
+    State(state): State<Arc<router::State>>,
+    Query(options): Query<ListOptions>,
+    pagination: Pagination,
+) -> Result<impl IntoResponse, Error> {
+    let prefix = storage_key(&namespace, &options.prefix)?;
+    let namespace_prefix = storage_key(&namespace, "")?;
+
+    let keys = state
+        .persist
+        .list()
+        .context("Failed to list keys")?
+        .into_iter()
+        .filter(|storage_key| storage_key.starts_with(&prefix))
+        .sorted()
+        .filter_map(|storage_key| {
+            let Some(entry) = load(&state.persist, &storage_key) else {
+                state.kv.purge(&state.persist, &storage_key);
+                return None;
+            };
+            Some(KeyView {
+                key: storage_key.strip_prefix(&namespace_prefix)?.to_string(),
+                version: entry.version,
+                expires_at: entry.expires_at.map(rfc3339),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(pagination.slice(keys))
+}
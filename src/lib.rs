@@ -1,5 +1,8 @@
 #![feature(iter_map_windows)]
 
+pub mod assets;
+pub mod blocking_pool;
+pub mod cookies;
 pub mod day_00;
 pub mod day_01;
 pub mod day_04;
@@ -17,4 +20,11 @@ pub mod day_19;
 pub mod day_20;
 pub mod day_21;
 pub mod day_22;
+pub mod ids;
+pub mod json_stream;
+pub mod kv;
+pub mod pagination;
+pub mod pixel_expr;
+pub mod pokeapi;
 pub mod router;
+pub mod validation;
@@ -1,4 +1,5 @@
 use shuttle_persist::PersistInstance;
+use shuttle_runtime::SecretStore;
 use shuttlings_cch23::router::router;
 use sqlx::PgPool;
 
@@ -6,9 +7,10 @@ use sqlx::PgPool;
 async fn main(
     #[shuttle_persist::Persist] persist: PersistInstance,
     #[shuttle_shared_db::Postgres] pool: PgPool,
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
 ) -> shuttle_axum::ShuttleAxum {
 //    tracing_subscriber::fmt().without_time().init();
     sqlx::migrate!().run(&pool).await.unwrap();
 
-    Ok(router(persist, pool).into())
+    Ok(router(persist, pool, secrets).into())
 }
@@ -0,0 +1,268 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri, Query},
+    http::{request::Parts, HeaderValue, StatusCode, Uri},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    router::{Error, HttpError},
+    validation::{FieldError, ValidationFailed},
+};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 1000;
+// Offsets end up as `BIGINT` query parameters
+const MAX_OFFSET: usize = i64::MAX as usize;
+const CURSOR_PREFIX: &str = "offset:";
+const PAGINATION_KEYS: [&str; 5] = ["offset", "limit", "page", "page_size", "cursor"];
+
+#[derive(Debug, Deserialize, Default)]
+struct PageQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    page: Option<usize>,
+    page_size: Option<usize>,
+    cursor: Option<String>,
+    split: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Offset,
+    Page,
+    Cursor,
+}
+
+/// Pagination parameters shared by list endpoints, supporting `offset`/`limit`,
+/// `page`/`page_size` and opaque `cursor` styles plus an optional `split`.
+#[derive(Debug)]
+pub struct Pagination {
+    mode: Mode,
+    offset: usize,
+    limit: Option<usize>,
+    split: Option<usize>,
+    uri: Uri,
+}
+
+fn encode_cursor(offset: usize) -> String {
+    rbase64::encode(format!("{}{}", CURSOR_PREFIX, offset).as_bytes())
+}
+
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    let decoded = String::from_utf8(rbase64::decode(cursor).ok()?).ok()?;
+    decoded.strip_prefix(CURSOR_PREFIX)?.parse().ok()
+}
+
+impl TryFrom<(PageQuery, Uri)> for Pagination {
+    type Error = ValidationFailed;
+
+    fn try_from((query, uri): (PageQuery, Uri)) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        let mut invalid =
+            |field: &str, message: &str| errors.push(FieldError::new(0, field, message));
+
+        for (field, value) in [
+            ("limit", query.limit),
+            ("page", query.page),
+            ("page_size", query.page_size),
+            ("split", query.split),
+        ] {
+            if value == Some(0) {
+                invalid(field, "must be greater than zero");
+            }
+        }
+        for (field, value) in [("limit", query.limit), ("page_size", query.page_size)] {
+            if value.is_some_and(|value| value > MAX_PAGE_SIZE) {
+                invalid(field, &format!("must be at most {}", MAX_PAGE_SIZE));
+            }
+        }
+
+        let paged = query.page.is_some() || query.page_size.is_some();
+        if query.cursor.is_some() && (paged || query.offset.is_some()) {
+            invalid("cursor", "cannot be combined with offset or page");
+        }
+        if paged && query.offset.is_some() {
+            invalid("page", "cannot be combined with offset");
+        }
+
+        let cursor = query.cursor.as_deref().map(decode_cursor);
+        if let Some(None) = cursor {
+            invalid("cursor", "is not a valid cursor");
+        }
+
+        let size = query.page_size.or(query.limit).unwrap_or(DEFAULT_PAGE_SIZE);
+        let (field, offset) = match cursor.flatten() {
+            Some(offset) => ("cursor", Some(offset)),
+            None if paged => (
+                "page",
+                query.page.unwrap_or(1).saturating_sub(1).checked_mul(size),
+            ),
+            None => ("offset", Some(query.offset.unwrap_or_default())),
+        };
+        let offset = offset.filter(|offset| *offset <= MAX_OFFSET);
+        if offset.is_none() {
+            invalid(field, "is too large");
+        }
+
+        if !errors.is_empty() {
+            return Err(ValidationFailed { errors });
+        }
+
+        let offset = offset.unwrap_or_default();
+        let pagination = match cursor.flatten() {
+            Some(_) => Pagination {
+                mode: Mode::Cursor,
+                offset,
+                limit: Some(query.limit.unwrap_or(DEFAULT_PAGE_SIZE)),
+                split: query.split,
+                uri,
+            },
+            None if paged => Pagination {
+                mode: Mode::Page,
+                offset,
+                limit: Some(size),
+                split: query.split,
+                uri,
+            },
+            None => Pagination {
+                mode: Mode::Offset,
+                offset,
+                limit: query.limit,
+                split: query.split,
+                uri,
+            },
+        };
+
+        Ok(pagination)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Pagination {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<PageQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| HttpError::new(StatusCode::BAD_REQUEST, rejection.body_text()))?;
+        // Nested routers strip their prefix, links need the path the client actually used
+        let uri = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|original| original.0.clone())
+            .unwrap_or_else(|| parts.uri.clone());
+
+        Ok(Pagination::try_from((query, uri))?)
+    }
+}
+
+impl Pagination {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Pages an in memory list
+    pub fn slice<T>(&self, items: Vec<T>) -> Page<T> {
+        let total = items.len();
+        let items = items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(total))
+            .collect();
+        self.page(items, total)
+    }
+
+    /// Wraps items that were already paged, e.g. by a `LIMIT`/`OFFSET` query
+    pub fn page<T>(&self, items: Vec<T>, total: usize) -> Page<T> {
+        Page {
+            links: self.links(total),
+            items,
+            total,
+            split: self.split,
+        }
+    }
+
+    fn link(&self, params: &[(&str, String)], rel: &str) -> Result<String, Error> {
+        let mut query: Vec<(String, String)> =
+            serde_urlencoded::from_str(self.uri.query().unwrap_or_default())
+                .context("Failed to parse query")?;
+        query.retain(|(key, _)| !PAGINATION_KEYS.contains(&key.as_str()));
+        query.extend(
+            params
+                .iter()
+                .map(|(key, val)| (key.to_string(), val.clone())),
+        );
+        let query = serde_urlencoded::to_string(&query).context("Failed to encode query")?;
+
+        Ok(format!("<{}?{}>; rel=\"{}\"", self.uri.path(), query, rel))
+    }
+
+    fn links(&self, total: usize) -> Vec<String> {
+        let Some(limit) = self.limit else {
+            return Vec::new();
+        };
+        let last = total.saturating_sub(1) / limit * limit;
+        let next = self.offset.checked_add(limit).filter(|next| *next < total);
+        let prev = (self.offset > 0).then(|| self.offset.saturating_sub(limit));
+
+        let params = |offset: usize| match self.mode {
+            Mode::Offset => vec![("offset", offset.to_string()), ("limit", limit.to_string())],
+            Mode::Page => vec![
+                ("page", (offset / limit + 1).to_string()),
+                ("page_size", limit.to_string()),
+            ],
+            Mode::Cursor => vec![
+                ("cursor", encode_cursor(offset)),
+                ("limit", limit.to_string()),
+            ],
+        };
+
+        [
+            Some((0, "first")),
+            prev.map(|offset| (offset, "prev")),
+            next.map(|offset| (offset, "next")),
+            Some((last, "last")),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|(offset, rel)| self.link(&params(offset), rel).ok())
+        .collect()
+    }
+}
+
+pub struct Page<T> {
+    items: Vec<T>,
+    total: usize,
+    links: Vec<String>,
+    split: Option<usize>,
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> axum::response::Response {
+        debug!(total = self.total, links = ?self.links);
+
+        let mut response = match self.split {
+            Some(split) => Json(self.items.chunks(split).collect::<Vec<_>>()).into_response(),
+            None => Json(self.items).into_response(),
+        };
+
+        let headers = response.headers_mut();
+        headers.insert("X-Total-Count", HeaderValue::from(self.total));
+        if let Ok(link) = HeaderValue::from_str(&self.links.join(", ")) {
+            if !self.links.is_empty() {
+                headers.insert("Link", link);
+            }
+        }
+
+        response
+    }
+}
@@ -0,0 +1,383 @@
+use std::{iter::Peekable, str::Chars};
+
+// Parsing and evaluation recurse, so both bound how deep that can go
+const MAX_SOURCE_LEN: usize = 1024;
+const MAX_DEPTH: usize = 64;
+
+/// Channels an expression can refer to, RGB and luma are 0-255, hue is in
+/// degrees and saturation and value are 0-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+    H,
+    S,
+    V,
+    L,
+}
+
+impl Channel {
+    pub const COUNT: usize = 8;
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "r" | "red" => Channel::R,
+            "g" | "green" => Channel::G,
+            "b" | "blue" => Channel::B,
+            "a" | "alpha" => Channel::A,
+            "h" | "hue" => Channel::H,
+            "s" | "saturation" => Channel::S,
+            "v" | "value" => Channel::V,
+            "l" | "luma" => Channel::L,
+            _ => return None,
+        })
+    }
+}
+
+/// Every channel of an RGBA pixel, indexed by [`Channel`]
+pub fn channels([r, g, b, a]: [u8; 4]) -> [f64; Channel::COUNT] {
+    let (r, g, b, a) = (r as f64, g as f64, b as f64, a as f64);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let hue = if delta == 0f64 {
+        0f64
+    } else if max == r {
+        60f64 * ((g - b) / delta).rem_euclid(6f64)
+    } else if max == g {
+        60f64 * ((b - r) / delta + 2f64)
+    } else {
+        60f64 * ((r - g) / delta + 4f64)
+    };
+    let saturation = if max == 0f64 { 0f64 } else { delta / max };
+
+    [
+        r,
+        g,
+        b,
+        a,
+        hue,
+        saturation,
+        max / 255f64,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne => 3,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div => 5,
+        }
+    }
+
+    fn apply(self, lhs: f64, rhs: f64) -> f64 {
+        let truth = |value: bool| if value { 1f64 } else { 0f64 };
+        match self {
+            BinOp::Add => lhs + rhs,
+            BinOp::Sub => lhs - rhs,
+            BinOp::Mul => lhs * rhs,
+            BinOp::Div => lhs / rhs,
+            BinOp::Lt => truth(lhs < rhs),
+            BinOp::Le => truth(lhs <= rhs),
+            BinOp::Gt => truth(lhs > rhs),
+            BinOp::Ge => truth(lhs >= rhs),
+            BinOp::Eq => truth(lhs == rhs),
+            BinOp::Ne => truth(lhs != rhs),
+            BinOp::And => truth(lhs != 0f64 && rhs != 0f64),
+            BinOp::Or => truth(lhs != 0f64 || rhs != 0f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Channel(Channel),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(Box<Node>, BinOp, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, channels: &[f64; Channel::COUNT]) -> f64 {
+        match self {
+            Node::Number(number) => *number,
+            Node::Channel(channel) => channels[*channel as usize],
+            Node::Neg(node) => -node.eval(channels),
+            Node::Not(node) => (node.eval(channels) == 0f64) as u8 as f64,
+            Node::Binary(lhs, op, rhs) => op.apply(lhs.eval(channels), rhs.eval(channels)),
+        }
+    }
+}
+
+/// Arithmetic and comparisons over pixel channels, e.g. `r > g + b && a > 0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelExpr(Node);
+
+impl PixelExpr {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_SOURCE_LEN {
+            return Err(format!(
+                "expression is longer than {} bytes",
+                MAX_SOURCE_LEN
+            ));
+        }
+
+        let mut parser = Parser {
+            chars: source.chars().peekable(),
+            position: 0,
+            depth: 0,
+        };
+        let node = parser.expr(0)?;
+        parser.skip_whitespace();
+        match parser.chars.peek() {
+            Some(c) => Err(format!("unexpected `{}` at {}", c, parser.position)),
+            None => Ok(PixelExpr(node)),
+        }
+    }
+
+    pub fn eval(&self, channels: &[f64; Channel::COUNT]) -> f64 {
+        self.0.eval(channels)
+    }
+
+    pub fn matches(&self, channels: &[f64; Channel::COUNT]) -> bool {
+        self.eval(channels) != 0f64
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn bump(&mut self) -> Option<char> {
+        self.position += 1;
+        self.chars.next()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.chars.peek() == Some(&expected) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "expression is nested deeper than {} at {}",
+                MAX_DEPTH, self.position
+            ));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    /// Peeks the next binary operator and how many characters it spans
+    fn operator(&mut self) -> Result<Option<(BinOp, usize)>, String> {
+        self.skip_whitespace();
+        let mut lookahead = self.chars.clone();
+        let (Some(first), second) = (lookahead.next(), lookahead.next()) else {
+            return Ok(None);
+        };
+
+        let op = match (first, second) {
+            ('<', Some('=')) => (BinOp::Le, 2),
+            ('>', Some('=')) => (BinOp::Ge, 2),
+            ('=', Some('=')) => (BinOp::Eq, 2),
+            ('!', Some('=')) => (BinOp::Ne, 2),
+            ('&', Some('&')) => (BinOp::And, 2),
+            ('|', Some('|')) => (BinOp::Or, 2),
+            ('<', _) => (BinOp::Lt, 1),
+            ('>', _) => (BinOp::Gt, 1),
+            ('+', _) => (BinOp::Add, 1),
+            ('-', _) => (BinOp::Sub, 1),
+            ('*', _) => (BinOp::Mul, 1),
+            ('/', _) => (BinOp::Div, 1),
+            ('=' | '!' | '&' | '|', _) => {
+                return Err(format!("incomplete operator at {}", self.position))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(op))
+    }
+
+    // Precedence climbing, operators are left associative
+    fn expr(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        while let Some((op, len)) = self.operator()? {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            for _ in 0..len {
+                self.bump();
+            }
+            let rhs = self.expr(op.precedence())?;
+            lhs = Node::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        self.skip_whitespace();
+        let wrap = if self.eat('-') {
+            Node::Neg
+        } else if self.eat('!') {
+            Node::Not
+        } else {
+            return self.atom();
+        };
+
+        self.enter()?;
+        let node = self.unary()?;
+        self.leave();
+        Ok(wrap(Box::new(node)))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let start = self.position;
+        match self.chars.peek().copied() {
+            Some('(') => {
+                self.bump();
+                self.enter()?;
+                let node = self.expr(0)?;
+                self.leave();
+                self.skip_whitespace();
+                if !self.eat(')') {
+                    return Err(format!("unclosed `(` at {}", start));
+                }
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(&c) = self
+                    .chars
+                    .peek()
+                    .filter(|c| c.is_ascii_digit() || **c == '.')
+                {
+                    number.push(c);
+                    self.bump();
+                }
+                number
+                    .parse()
+                    .map(Node::Number)
+                    .map_err(|_| format!("invalid number `{}` at {}", number, start))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    name.push(c.to_ascii_lowercase());
+                    self.bump();
+                }
+                Channel::parse(&name)
+                    .map(Node::Channel)
+                    .ok_or_else(|| format!("unknown channel `{}` at {}", name, start))
+            }
+            Some(c) => Err(format!("unexpected `{}` at {}", c, start)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, pixel: [u8; 4]) -> f64 {
+        PixelExpr::parse(source).unwrap().eval(&channels(pixel))
+    }
+
+    #[test]
+    fn follows_precedence_and_associativity() {
+        let pixel = [0, 0, 0, 0];
+        assert_eq!(eval("1 + 2 * 3", pixel), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", pixel), 9.0);
+        assert_eq!(eval("8 - 4 - 2", pixel), 2.0);
+        assert_eq!(eval("8 / 4 / 2", pixel), 1.0);
+        assert_eq!(eval("-2 * -3", pixel), 6.0);
+        assert_eq!(eval("1 < 2 && 3 >= 3 || 0", pixel), 1.0);
+        assert_eq!(eval("1 + 1 == 2", pixel), 1.0);
+        assert_eq!(eval("!0 && !!1", pixel), 1.0);
+        assert_eq!(eval(".5 + 1.25", pixel), 1.75);
+    }
+
+    #[test]
+    fn reads_channels_by_short_and_long_name() {
+        let pixel = [200, 10, 20, 255];
+        assert_eq!(eval("r", pixel), 200.0);
+        assert_eq!(eval("Green + BLUE", pixel), 30.0);
+        assert_eq!(eval("alpha", pixel), 255.0);
+        assert!(PixelExpr::parse("red > green + blue && a > 0")
+            .unwrap()
+            .matches(&channels(pixel)));
+        assert!(!PixelExpr::parse("r < g").unwrap().matches(&channels(pixel)));
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let error = |source: &str| PixelExpr::parse(source).unwrap_err();
+        assert_eq!(error(""), "unexpected end of expression");
+        assert_eq!(error("r +"), "unexpected end of expression");
+        assert_eq!(error("rouge > 1"), "unknown channel `rouge` at 0");
+        assert_eq!(error("r = g"), "incomplete operator at 2");
+        assert_eq!(error("r & g"), "incomplete operator at 2");
+        assert_eq!(error("(r + g"), "unclosed `(` at 0");
+        assert_eq!(error("r g"), "unexpected `g` at 2");
+        assert_eq!(error("1.2.3"), "invalid number `1.2.3` at 0");
+        assert_eq!(error("r > #"), "unexpected `#` at 4");
+    }
+
+    #[test]
+    fn bounds_length_and_nesting() {
+        let longest = format!("{}1", "1+".repeat(MAX_SOURCE_LEN / 2 - 1));
+        assert!(PixelExpr::parse(&longest).is_ok());
+        assert!(PixelExpr::parse(&"1".repeat(MAX_SOURCE_LEN + 1))
+            .unwrap_err()
+            .contains("longer than"));
+
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(PixelExpr::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(PixelExpr::parse(&nested(MAX_DEPTH + 1))
+            .unwrap_err()
+            .contains("nested deeper"));
+        assert!(PixelExpr::parse(&format!("{}1", "-".repeat(MAX_DEPTH + 1)))
+            .unwrap_err()
+            .contains("nested deeper"));
+        assert!(PixelExpr::parse(&format!("{}1", "!".repeat(MAX_DEPTH))).is_ok());
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use lru::LruCache;
+use reqwest::header::CACHE_CONTROL;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use shuttle_runtime::SecretStore;
+use tracing::{debug, info, warn};
+
+use crate::{
+    router::{secret, Error, HttpError},
+    validation::invalid,
+};
+
+const DEFAULT_BASE_URL: &str = "https://pokeapi.co/api/v2";
+const BUNDLED_DATASET: &str = include_str!("../data/pokemon.csv");
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct PokeApiConfig {
+    pub base_url: String,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: Duration,
+    pub cache_size: NonZeroUsize,
+    pub cache_dir: Option<PathBuf>,
+    /// `bundled` or a path to a JSON or CSV file
+    pub dataset: Option<String>,
+    /// Ask the live API for Pokémon missing from the dataset
+    pub live_fallback: bool,
+}
+
+impl Default for PokeApiConfig {
+    fn default() -> Self {
+        PokeApiConfig {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: Duration::from_millis(200),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+            cache_size: NonZeroUsize::new(256).unwrap(),
+            cache_dir: None,
+            dataset: None,
+            live_fallback: false,
+        }
+    }
+}
+
+impl PokeApiConfig {
+    /// Reads `POKEAPI_*` secrets, anything missing keeps its default
+    pub fn from_secrets(secrets: &SecretStore) -> Self {
+        let default = PokeApiConfig::default();
+        let millis = |key| secret(secrets, key).map(Duration::from_millis);
+
+        PokeApiConfig {
+            base_url: secrets
+                .get("POKEAPI_BASE_URL")
+                .map(|url| url.trim_end_matches('/').to_string())
+                .unwrap_or(default.base_url),
+            timeout: millis("POKEAPI_TIMEOUT_MS").unwrap_or(default.timeout),
+            retries: secret(secrets, "POKEAPI_RETRIES").unwrap_or(default.retries),
+            backoff: millis("POKEAPI_BACKOFF_MS").unwrap_or(default.backoff),
+            breaker_threshold: secret(secrets, "POKEAPI_BREAKER_THRESHOLD")
+                .unwrap_or(default.breaker_threshold),
+            breaker_cooldown: millis("POKEAPI_BREAKER_COOLDOWN_MS")
+                .unwrap_or(default.breaker_cooldown),
+            cache_size: secret(secrets, "POKEAPI_CACHE_SIZE").unwrap_or(default.cache_size),
+            cache_dir: secrets.get("POKEAPI_CACHE_DIR").map(PathBuf::from),
+            dataset: secrets.get("POKEAPI_DATASET"),
+            live_fallback: secret(secrets, "POKEAPI_LIVE_FALLBACK")
+                .unwrap_or(default.live_fallback),
+        }
+    }
+}
+
+// Untyped cells, numbers stay numbers so entries deserialize like API responses
+fn csv_cell(cell: &str) -> Value {
+    cell.parse::<i64>()
+        .map(Value::from)
+        .or_else(|_| cell.parse::<f64>().map(Value::from))
+        .unwrap_or_else(|_| Value::from(cell))
+}
+
+fn parse_csv(csv: &str) -> Result<Vec<Value>, anyhow::Error> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header = lines
+        .next()
+        .context("Dataset is empty")?
+        .split(',')
+        .map(|column| column.trim().to_string())
+        .collect::<Vec<_>>();
+
+    lines
+        .enumerate()
+        .map(|(row, line)| {
+            let cells = line.split(',').map(str::trim).collect::<Vec<_>>();
+            anyhow::ensure!(
+                cells.len() == header.len(),
+                "Dataset row {} has {} columns, expected {}",
+                row + 1,
+                cells.len(),
+                header.len()
+            );
+            Ok(header
+                .iter()
+                .cloned()
+                .zip(cells.into_iter().map(csv_cell))
+                .collect())
+        })
+        .collect()
+}
+
+/// Pokémon loaded at startup, looked up by id or lowercase name
+pub struct Dataset {
+    entries: Vec<Value>,
+    index: HashMap<String, usize>,
+}
+
+impl Dataset {
+    pub fn load(source: &str) -> Result<Self, anyhow::Error> {
+        let entries = match source {
+            "bundled" => parse_csv(BUNDLED_DATASET)?,
+            path => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read dataset {}", path))?;
+                if path.ends_with(".json") {
+                    serde_json::from_str(&contents).context("Failed to parse dataset json")?
+                } else if path.ends_with(".csv") {
+                    parse_csv(&contents)?
+                } else {
+                    anyhow::bail!("Dataset {} is neither .json nor .csv", path);
+                }
+            }
+        };
+
+        let mut index = HashMap::new();
+        for (position, entry) in entries.iter().enumerate() {
+            let id = match &entry["id"] {
+                Value::Number(id) => id.to_string(),
+                Value::String(id) => id.clone(),
+                _ => anyhow::bail!("Dataset entry {} has no id", position),
+            };
+            index.insert(id, position);
+            if let Some(name) = entry["name"].as_str() {
+                index.insert(name.to_ascii_lowercase(), position);
+            }
+        }
+
+        info!(source, entries = entries.len(), "Loaded pokemon dataset");
+        Ok(Dataset { entries, index })
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.index.get(key).map(|&position| &self.entries[position])
+    }
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    body: String,
+    // Unix seconds, so entries written to disk stay meaningful across restarts
+    expires_at: u64,
+}
+
+impl CachedResponse {
+    fn is_fresh(&self) -> bool {
+        self.expires_at > unix_now()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// How long a response may be cached for, `None` when it must not be stored
+fn max_age(cache_control: &str) -> Option<u64> {
+    let directives = cache_control
+        .split(',')
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    if directives
+        .iter()
+        .any(|directive| directive == "no-store" || directive == "no-cache")
+    {
+        return None;
+    }
+
+    directives
+        .iter()
+        .filter_map(|directive| directive.strip_prefix("max-age="))
+        .find_map(|age| age.parse().ok())
+        .filter(|&age| age > 0)
+}
+
+enum Attempt {
+    Done(Result<(String, Option<u64>), Error>),
+    Retry(anyhow::Error),
+}
+
+/// PokeAPI client with a timeout, retries with exponential backoff, a circuit
+/// breaker and an in memory LRU backed by an optional on-disk cache.
+pub struct PokeApi {
+    config: PokeApiConfig,
+    client: reqwest::Client,
+    cache: Mutex<LruCache<String, CachedResponse>>,
+    breaker: Mutex<Breaker>,
+    dataset: Option<Dataset>,
+}
+
+impl PokeApi {
+    pub fn new(config: PokeApiConfig) -> Result<Self, anyhow::Error> {
+        info!(?config);
+        let dataset = config.dataset.as_deref().map(Dataset::load).transpose()?;
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("Failed to build pokeapi client");
+
+        Ok(PokeApi {
+            cache: Mutex::new(LruCache::new(config.cache_size)),
+            breaker: Mutex::default(),
+            client,
+            dataset,
+            config,
+        })
+    }
+
+    pub async fn pokemon<T: DeserializeOwned>(&self, id: &str) -> Result<T, Error> {
+        let id = id.to_ascii_lowercase();
+        // Ends up as a path segment upstream, so nothing that could change the URL gets through
+        let valid = !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(invalid(
+                0,
+                "pokemon",
+                "must be a name or id of letters, digits and `-`",
+            )
+            .into());
+        }
+        if let Some(dataset) = &self.dataset {
+            match dataset.get(&id) {
+                Some(entry) => {
+                    return Ok(T::deserialize(entry).context("Dataset entry is incomplete")?)
+                }
+                None if !self.config.live_fallback => {
+                    return Err(HttpError::not_found("Pokémon not found").into())
+                }
+                None => debug!(id, "Not in dataset, asking pokeapi"),
+            }
+        }
+
+        let body = self.get(&format!("pokemon/{}", id)).await?;
+        Ok(serde_json::from_str(&body).context("Failed to parse response json")?)
+    }
+
+    async fn get(&self, path: &str) -> Result<String, Error> {
+        if let Some(body) = self.cached(path).await {
+            debug!(path, "pokeapi cache hit");
+            return Ok(body);
+        }
+
+        self.check_breaker()?;
+        let url = format!("{}/{}", self.config.base_url, path);
+        let mut attempt = 0;
+        let result = loop {
+            match self.attempt(&url).await {
+                Attempt::Done(result) => break Ok(result),
+                Attempt::Retry(err) if attempt >= self.config.retries => break Err(err),
+                Attempt::Retry(err) => {
+                    let delay = self
+                        .config
+                        .backoff
+                        .checked_mul(2u32.pow(attempt.min(16)))
+                        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF));
+                    warn!(url, attempt, ?delay, "Retrying pokeapi request: {:#}", err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        };
+
+        self.record(result.is_ok());
+        let (body, max_age) = result??;
+        if let Some(max_age) = max_age {
+            self.store(
+                path,
+                CachedResponse {
+                    body: body.clone(),
+                    expires_at: unix_now() + max_age,
+                },
+            )
+            .await;
+        }
+
+        Ok(body)
+    }
+
+    async fn attempt(&self, url: &str) -> Attempt {
+        let response = match self.client.get(url).send().await {
+            Ok(response) => response,
+            Err(err) => return Attempt::Retry(anyhow::Error::new(err)),
+        };
+
+        let status = response.status();
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Attempt::Retry(anyhow::anyhow!("pokeapi responded with {}", status));
+        }
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Attempt::Done(Err(HttpError::not_found("Pokémon not found").into()));
+        }
+        if !status.is_success() {
+            return Attempt::Done(Err(
+                anyhow::anyhow!("pokeapi responded with {}", status).into()
+            ));
+        }
+
+        let max_age = response
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(max_age);
+        match response.text().await {
+            Ok(body) => Attempt::Done(Ok((body, max_age))),
+            Err(err) => Attempt::Retry(anyhow::Error::new(err)),
+        }
+    }
+
+    fn check_breaker(&self) -> Result<(), HttpError> {
+        let mut breaker = self.breaker.lock().unwrap();
+        match breaker.open_until {
+            Some(until) if until > Instant::now() => Err(HttpError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "pokeapi is unavailable, try again later",
+            )),
+            Some(_) => {
+                // Half open, let this request through as a probe
+                info!("pokeapi circuit half open");
+                breaker.open_until = None;
+                breaker.failures = self.config.breaker_threshold.saturating_sub(1);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Only upstream failures count, a 404 for an unknown Pokémon is a success here
+    fn record(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            *breaker = Breaker::default();
+            return;
+        }
+
+        breaker.failures += 1;
+        if breaker.failures >= self.config.breaker_threshold {
+            warn!(failures = breaker.failures, "pokeapi circuit open");
+            breaker.open_until = Some(Instant::now() + self.config.breaker_cooldown);
+        }
+    }
+
+    fn cache_file(&self, path: &str) -> Option<PathBuf> {
+        let dir = self.config.cache_dir.as_ref()?;
+        Some(dir.join(format!("{}.json", sha256::digest(path))))
+    }
+
+    async fn cached(&self, path: &str) -> Option<String> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            match cache.get(path) {
+                Some(entry) if entry.is_fresh() => return Some(entry.body.clone()),
+                Some(_) => {
+                    cache.pop(path);
+                }
+                None => {}
+            }
+        }
+
+        let file = self.cache_file(path)?;
+        let entry: CachedResponse = serde_json::from_slice(&tokio::fs::read(&file).await.ok()?)
+            .map_err(|err| warn!(?file, "Ignoring corrupt cache file: {}", err))
+            .ok()?;
+        if !entry.is_fresh() {
+            let _ = tokio::fs::remove_file(&file).await;
+            return None;
+        }
+
+        let body = entry.body.clone();
+        self.cache.lock().unwrap().put(path.to_string(), entry);
+        Some(body)
+    }
+
+    async fn store(&self, path: &str, entry: CachedResponse) {
+        if let Some(file) = self.cache_file(path) {
+            let written = async {
+                let json = serde_json::to_vec(&entry)?;
+                if let Some(dir) = file.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                tokio::fs::write(&file, json).await?;
+                anyhow::Ok(())
+            };
+            if let Err(err) = written.await {
+                warn!(?file, "Failed to write cache file: {:#}", err);
+            }
+        }
+
+        self.cache.lock().unwrap().put(path.to_string(), entry);
+    }
+}
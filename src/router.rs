@@ -1,58 +1,175 @@
 use axum::{
     http::StatusCode,
+    middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
 use country_boundaries::{CountryBoundaries, BOUNDARIES_ODBL_360X180};
 use derive_more::{Display, From};
 use shuttle_persist::PersistInstance;
+use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 use tower_http::services::ServeDir;
-use tracing::warn;
+use tracing::{info, warn};
 
 use crate::{
-    day_00, day_01, day_04, day_05, day_06, day_07, day_08, day_11, day_12, day_13, day_14, day_15,
-    day_18, day_19, day_20, day_21, day_22,
+    assets::{self, Assets, AssetsConfig},
+    blocking_pool::BlockingPool,
+    cookies::CookieKeys,
+    day_00, day_01, day_04, day_05, day_06, day_07, day_08, day_11,
+    day_11::ImageLimits,
+    day_12,
+    day_12::TimerStore,
+    day_13, day_14, day_15, day_18, day_19, day_20, day_21, day_22,
+    ids::{self, UlidGenerator},
+    kv::{self, KvStore},
+    pokeapi::{PokeApi, PokeApiConfig},
+    validation::ValidationFailed,
 };
 
 pub struct State {
-    pub client: reqwest::Client,
+    pub assets: Assets,
+    pub pokeapi: PokeApi,
+    pub image_limits: ImageLimits,
+    pub image_pool: BlockingPool,
+    pub kv: KvStore,
+    pub ulid_generator: UlidGenerator,
+    pub timers: TimerStore,
     pub persist: PersistInstance,
     pub pool: PgPool,
     pub boundaries: CountryBoundaries,
+    pub cookie_keys: CookieKeys,
 }
 
-pub fn router(persist: PersistInstance, pool: PgPool) -> Router {
+/// Parses an optional secret, unparseable values are logged and treated as missing
+pub fn secret<T: FromStr>(secrets: &SecretStore, key: &str) -> Option<T> {
+    let value = secrets.get(key)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        warn!(key, value, "Ignoring unparseable secret");
+    }
+    parsed
+}
+
+pub fn router(persist: PersistInstance, pool: PgPool, secrets: SecretStore) -> Router {
+    let cookie_keys = secrets
+        .get("COOKIE_KEYS")
+        .map(|keys| CookieKeys::from_config(&keys).expect("Invalid COOKIE_KEYS secret"))
+        .unwrap_or_else(CookieKeys::generate)
+        .with_legacy(secret(&secrets, "COOKIE_ALLOW_LEGACY").unwrap_or_default());
+
     let state = Arc::new(State {
-        client: reqwest::Client::new(),
+        assets: Assets::new(AssetsConfig::from_secrets(&secrets)),
+        pokeapi: PokeApi::new(PokeApiConfig::from_secrets(&secrets))
+            .expect("Failed to load pokeapi dataset"),
+        image_limits: ImageLimits::from_secrets(&secrets),
+        image_pool: BlockingPool::new(
+            secret(&secrets, "IMAGE_WORKERS").unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |workers| workers.get())
+            }),
+            secret(&secrets, "IMAGE_MAX_QUEUE").unwrap_or(32),
+        ),
+        kv: KvStore::from_secrets(&secrets),
+        ulid_generator: UlidGenerator::default(),
+        timers: TimerStore::default(),
         persist,
         pool,
         boundaries: CountryBoundaries::from_reader(BOUNDARIES_ODBL_360X180).unwrap(),
+        cookie_keys,
     });
 
+    let serve_dir = ServeDir::new(&state.assets.config.root)
+        .precompressed_gzip()
+        .precompressed_br();
+    let assets = Router::new()
+        .route(
+            "/*path",
+            put(assets::upload)
+                .delete(assets::remove)
+                .fallback_service(serve_dir.clone()),
+        )
+        .fallback_service(serve_dir)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            assets::caching,
+        ));
+
     Router::new()
         .route("/", get(day_00::task_01))
         .route("/-1/error", get(day_00::task_02))
         .route("/1/*x", get(day_01::task_00))
         .route("/4/strength", post(day_04::task_01))
         .route("/4/contest", post(day_04::task_02))
+        .route("/4/stats", post(day_04::stats))
+        .route(
+            "/4/reindeer",
+            get(day_04::registry_list).post(day_04::registry_create),
+        )
+        .route(
+            "/4/reindeer/:name",
+            get(day_04::registry_get)
+                .put(day_04::registry_update)
+                .delete(day_04::registry_delete),
+        )
+        .route("/4/contest/custom", post(day_04::contest_custom))
+        .route("/4/contest/specs", get(day_04::spec_list))
+        .route(
+            "/4/contest/specs/:name",
+            get(day_04::spec_get).put(day_04::spec_put),
+        )
+        .route(
+            "/4/contests",
+            get(day_04::contest_history).post(day_04::contest_run),
+        )
         .route("/5", post(day_05::task_00))
         .route("/6", post(day_06::task_00))
+        .route("/6/count", post(day_06::count))
+        .route("/6/analyze", post(day_06::analyze))
         .route("/7/decode", get(day_07::task_01))
         .route("/7/bake", get(day_07::task_02))
+        .route("/7/cookie", post(day_07::issue))
+        .route("/7/plan", post(day_07::plan))
+        .route(
+            "/7/pantry/:user",
+            get(day_07::pantry_get).post(day_07::pantry_restock),
+        )
+        .route(
+            "/7/bake/:user",
+            get(day_07::pantry_history).post(day_07::pantry_bake),
+        )
         .route("/8/weight/:number", get(day_08::task_01))
         .route("/8/drop/:number", get(day_08::task_02))
-        .nest_service("/11/assets/", ServeDir::new("assets"))
+        .route("/8/physics/:pokemon", get(day_08::physics))
+        .route("/8/compare", get(day_08::compare))
+        .nest("/11/assets", assets)
         .route("/11/red_pixels", post(day_11::task_02))
+        .route("/11/analyze", post(day_11::analyze))
+        .route("/11/transform", post(day_11::transform))
+        .route("/11/pool", get(day_11::pool_metrics))
         .route("/12/save/:id", post(day_12::task_01_save))
         .route("/12/load/:id", get(day_12::task_01_load))
+        .route(
+            "/12/timers",
+            get(day_12::list_timers).post(day_12::create_timer),
+        )
+        .route(
+            "/12/timers/:id",
+            get(day_12::get_timer).delete(day_12::delete_timer),
+        )
+        .route("/12/timers/:id/pause", post(day_12::pause_timer))
+        .route("/12/timers/:id/resume", post(day_12::resume_timer))
+        .route("/12/timers/:id/laps", post(day_12::lap_timer))
         .route("/12/ulids", post(day_12::task_02))
+        .route("/12/ulids/query", post(day_12::query_ulids))
         .route("/12/ulids/:day", post(day_12::task_03))
         .route("/13/sql", get(day_13::task_01))
         .route("/13/reset", post(day_13::task_02_reset))
-        .route("/13/orders", post(day_13::task_02_orders))
+        .route(
+            "/13/orders",
+            get(day_13::list_orders).post(day_13::task_02_orders),
+        )
         .route("/13/orders/total", get(day_13::task_02_total))
         .route("/13/orders/popular", get(day_13::task_03_popular))
         .route("/14/unsafe", post(day_14::task_01))
@@ -61,7 +178,10 @@ pub fn router(persist: PersistInstance, pool: PgPool) -> Router {
         .route("/15/game", post(day_15::task_02))
         .route("/18/reset", post(day_18::task_01_reset))
         .route("/18/orders", post(day_18::task_01_orders))
-        .route("/18/regions", post(day_18::task_01_regions))
+        .route(
+            "/18/regions",
+            get(day_18::list_regions).post(day_18::task_01_regions),
+        )
         .route("/18/regions/total", get(day_18::task_01_total))
         .route("/18/regions/top_list/:number", get(day_18::task_02))
         .nest_service("/19", day_19::router())
@@ -72,15 +192,60 @@ pub fn router(persist: PersistInstance, pool: PgPool) -> Router {
         .route("/21/country/:binary", get(day_21::task_02))
         .route("/22/integers", post(day_22::task_01))
         .route("/22/rocket", post(day_22::task_02))
+        .route("/ids/generate", get(ids::generate))
+        .route("/ids/convert", post(ids::convert))
+        .route("/ids/inspect", post(ids::inspect))
+        .route("/ids/validate", post(ids::validate))
+        .route("/kv/:namespace", get(kv::list))
+        .route(
+            "/kv/:namespace/:key",
+            get(kv::get).put(kv::put).delete(kv::delete),
+        )
         .with_state(state)
 }
 
 #[derive(Display, Debug, From)]
 pub struct Error(anyhow::Error);
 
+#[derive(Display, Debug)]
+#[display(fmt = "{}", message)]
+pub struct HttpError {
+    status: StatusCode,
+    message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        HttpError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        HttpError::new(StatusCode::NOT_FOUND, message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<HttpError> for Error {
+    fn from(value: HttpError) -> Self {
+        anyhow::Error::new(value).into()
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        warn!("{:?} Error occured", self);
-        (StatusCode::INTERNAL_SERVER_ERROR, self.0.to_string()).into_response()
+        let err = match self.0.downcast::<ValidationFailed>() {
+            Ok(invalid) => return invalid.into_response(),
+            Err(err) => err,
+        };
+        if let Some(http) = err.downcast_ref::<HttpError>() {
+            info!(?http);
+            return (http.status, http.message.clone()).into_response();
+        }
+        warn!("{:?} Error occured", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
     }
 }
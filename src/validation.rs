@@ -0,0 +1,86 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use derive_more::Display;
+use serde::Serialize;
+use tracing::info;
+use validator::Validate;
+
+use crate::router::Error;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct FieldError {
+    pub index: usize,
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Display, Serialize)]
+#[display(fmt = "{} invalid field(s)", "errors.len()")]
+pub struct ValidationFailed {
+    pub errors: Vec<FieldError>,
+}
+
+impl FieldError {
+    pub fn new(index: usize, field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            index,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::error::Error for ValidationFailed {}
+
+impl IntoResponse for ValidationFailed {
+    fn into_response(self) -> axum::response::Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+impl From<ValidationFailed> for Error {
+    fn from(value: ValidationFailed) -> Self {
+        anyhow::Error::new(value).into()
+    }
+}
+
+/// A single invalid field, for checks that stop at the first problem
+pub fn invalid(index: usize, field: &str, message: impl Into<String>) -> ValidationFailed {
+    ValidationFailed {
+        errors: vec![FieldError::new(index, field, message)],
+    }
+}
+
+// Checks every item up front so a payload is either accepted whole or rejected before any SQL runs
+pub fn validate_all<T: Validate>(items: &[T]) -> Result<(), ValidationFailed> {
+    let mut errors = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| item.validate().err().map(|err| (index, err)))
+        .flat_map(|(index, err)| {
+            err.field_errors()
+                .into_iter()
+                .flat_map(|(field, errs)| {
+                    errs.iter()
+                        .map(|err| {
+                            let message = err
+                                .message
+                                .as_ref()
+                                .map(|message| message.to_string())
+                                .unwrap_or_else(|| err.code.to_string());
+                            FieldError::new(index, field.to_string(), message)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    errors.sort_by(|a, b| a.index.cmp(&b.index).then_with(|| a.field.cmp(&b.field)));
+    info!(?errors);
+
+    Err(ValidationFailed { errors })
+}